@@ -0,0 +1,162 @@
+use crate::color;
+use crate::filter::Frame;
+
+pub const HISTOGRAM_BINS: usize = 256;
+
+/// Relative luminance at or above which a pixel counts as near white.
+pub const NEAR_WHITE: f32 = 0.8;
+
+/// Rec.709 relative luminance statistics of a frame. Luminance is linear
+/// and ranges from 0 (black) to 1 (white).
+#[derive(Clone, Debug)]
+pub struct LuminanceStats {
+    pub mean: f32,
+    pub histogram: [u32; HISTOGRAM_BINS],
+    pub count: u32,
+    /// Fraction of pixels at or above [`NEAR_WHITE`].
+    pub near_white: f32,
+}
+
+impl LuminanceStats {
+    pub fn from_frame(frame: &Frame) -> Self {
+        Self::from_luminances(frame.pixels().map(|p| pixel_luminance([p[0], p[1], p[2]])))
+    }
+
+    pub fn from_luminances<I: IntoIterator<Item = f32>>(values: I) -> Self {
+        let mut histogram = [0; HISTOGRAM_BINS];
+        let mut sum = 0.0f64;
+        let mut count = 0u32;
+        let mut near_white = 0u32;
+        for y in values {
+            let y = y.clamp(0.0, 1.0);
+            sum += y as f64;
+            count += 1;
+            if y >= NEAR_WHITE {
+                near_white += 1;
+            }
+            histogram[bin(y)] += 1;
+        }
+        let total = count.max(1) as f32;
+        LuminanceStats {
+            mean: (sum / total as f64) as f32,
+            histogram,
+            count,
+            near_white: near_white as f32 / total,
+        }
+    }
+
+    /// Luminance below which `p` percent of the pixels fall.
+    pub fn percentile(&self, p: f32) -> f32 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let target = (p.clamp(0.0, 100.0) / 100.0 * self.count as f32)
+            .ceil()
+            .max(1.0) as u32;
+        let mut seen = 0;
+        for (i, &n) in self.histogram.iter().enumerate() {
+            seen += n;
+            if seen >= target {
+                return (i as f32 + 0.5) / HISTOGRAM_BINS as f32;
+            }
+        }
+        1.0
+    }
+
+    pub fn median(&self) -> f32 {
+        self.percentile(50.0)
+    }
+}
+
+/// Mean relative luminance of a frame, without the rest of
+/// [`LuminanceStats`].
+pub fn mean_luminance(frame: &Frame) -> f32 {
+    let sum = frame
+        .pixels()
+        .map(|p| pixel_luminance([p[0], p[1], p[2]]) as f64)
+        .sum::<f64>();
+    (sum / (frame.width * frame.height).max(1) as f64) as f32
+}
+
+/// Luminance of a BGR pixel.
+pub fn pixel_luminance([b, g, r]: [u8; 3]) -> f32 {
+    color::luminance([color::decode(r), color::decode(g), color::decode(b)])
+}
+
+fn bin(y: f32) -> usize {
+    ((y * HISTOGRAM_BINS as f32) as usize).min(HISTOGRAM_BINS - 1)
+}
+
+/// Which number to take from [`LuminanceStats`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Statistic {
+    Mean,
+    Median,
+    Percentile(f32),
+    NearWhite,
+}
+
+impl Statistic {
+    pub fn measure(&self, stats: &LuminanceStats) -> f32 {
+        match *self {
+            Statistic::Mean => stats.mean,
+            Statistic::Median => stats.median(),
+            Statistic::Percentile(p) => stats.percentile(p),
+            Statistic::NearWhite => stats.near_white,
+        }
+    }
+}
+
+/// Decides whether a frame is bright enough to be darkened.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Policy {
+    pub statistic: Statistic,
+    pub threshold: f32,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            statistic: Statistic::Mean,
+            threshold: 0.18,
+        }
+    }
+}
+
+impl Policy {
+    pub fn should_filter(&self, stats: &LuminanceStats) -> bool {
+        self.statistic.measure(stats) > self.threshold
+    }
+
+    /// Parses policies such as `mean>0.18`, `median>0.5`, `p90>0.7` or
+    /// `near-white>0.4`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, threshold) = spec
+            .split_once('>')
+            .ok_or_else(|| format!("policy '{}' should look like 'mean>0.18'", spec))?;
+        let threshold = threshold
+            .trim()
+            .parse::<f32>()
+            .map_err(|_| format!("invalid threshold '{}' in policy '{}'", threshold, spec))?;
+        let statistic = match name.trim() {
+            "mean" => Statistic::Mean,
+            "median" => Statistic::Median,
+            "near-white" => Statistic::NearWhite,
+            p if p.starts_with('p') => Statistic::Percentile(
+                p[1..]
+                    .parse::<f32>()
+                    .map_err(|_| format!("invalid percentile '{}' in policy '{}'", p, spec))?,
+            ),
+            other => {
+                return Err(format!(
+                    "unknown statistic '{}' in policy '{}'",
+                    other, spec
+                ))
+            }
+        };
+        Ok(Policy {
+            statistic,
+            threshold,
+        })
+    }
+}
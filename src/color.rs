@@ -0,0 +1,165 @@
+use std::sync::OnceLock;
+
+const ENCODE_STEPS: usize = 8192;
+
+fn decode_table() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (i, v) in table.iter_mut().enumerate() {
+            *v = srgb_to_linear(i as f32 / 255.0);
+        }
+        table
+    })
+}
+
+fn encode_table() -> &'static [f32; ENCODE_STEPS + 2] {
+    static TABLE: OnceLock<[f32; ENCODE_STEPS + 2]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [1.0; ENCODE_STEPS + 2];
+        for (i, v) in table.iter_mut().take(ENCODE_STEPS + 1).enumerate() {
+            *v = linear_to_srgb(i as f32 / ENCODE_STEPS as f32);
+        }
+        table
+    })
+}
+
+pub(crate) fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub(crate) fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Linear value of an sRGB encoded byte.
+pub(crate) fn decode(v: u8) -> f32 {
+    decode_table()[v as usize]
+}
+
+/// sRGB encoding of a linear value, from 0 to 1. Faster than
+/// [`linear_to_srgb`] and accurate to well below one 8-bit step.
+pub(crate) fn encode_unit(c: f32) -> f32 {
+    let p = c.clamp(0.0, 1.0) * ENCODE_STEPS as f32;
+    let i = p as usize;
+    let table = encode_table();
+    table[i] + (table[i + 1] - table[i]) * (p - i as f32)
+}
+
+/// sRGB encoded byte of a linear value.
+pub(crate) fn encode(c: f32) -> u8 {
+    (encode_unit(c) * 255.0).round() as u8
+}
+
+/// Rec.709 relative luminance of linear RGB.
+pub(crate) fn luminance([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+pub(crate) fn linear_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+    [
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    ]
+}
+
+pub(crate) fn oklab_to_linear([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let l_ = l + 0.396_337_78 * a + 0.215_803_76 * b;
+    let m_ = l - 0.105_561_346 * a - 0.063_854_17 * b;
+    let s_ = l - 0.089_484_18 * a - 1.291_485_5 * b;
+    let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+    [
+        4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s,
+        -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s,
+        -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s,
+    ]
+}
+
+/// Hermite step from 0 at `low` to 1 at `high`.
+pub(crate) fn smoothstep(low: f32, high: f32, x: f32) -> f32 {
+    let t = ((x - low) / (high - low)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn in_gamut(rgb: [f32; 3]) -> bool {
+    const EPS: f32 = 1e-4;
+    rgb.iter().all(|&c| (-EPS..=1.0 + EPS).contains(&c))
+}
+
+/// Inverts OKLab lightness while keeping hue. Chroma is kept too unless the
+/// result falls outside sRGB, in which case it is reduced until it fits.
+pub(crate) fn invert_lightness_linear(rgb: [f32; 3]) -> [f32; 3] {
+    map_lightness_linear(rgb, |l| 1.0 - l)
+}
+
+/// Replaces OKLab lightness with `f(lightness)` while keeping hue, reducing
+/// chroma only as far as needed to stay inside sRGB.
+pub(crate) fn map_lightness_linear(rgb: [f32; 3], f: impl Fn(f32) -> f32) -> [f32; 3] {
+    let [l, a, b] = linear_to_oklab(rgb);
+    let l = f(l);
+    let out = oklab_to_linear([l, a, b]);
+    if in_gamut(out) {
+        return out;
+    }
+    let (mut lo, mut hi) = (0.0, 1.0);
+    for _ in 0..10 {
+        let mid = (lo + hi) / 2.0;
+        if in_gamut(oklab_to_linear([l, a * mid, b * mid])) {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    oklab_to_linear([l, a * lo, b * lo])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hue([_, a, b]: [f32; 3]) -> f32 {
+        b.atan2(a)
+    }
+
+    fn chroma([_, a, b]: [f32; 3]) -> f32 {
+        a.hypot(b)
+    }
+
+    #[test]
+    fn invert_lightness_round_trips_in_gamut() {
+        for rgb in [[0.2, 0.3, 0.4], [0.5, 0.5, 0.5], [0.0, 0.0, 0.0]] {
+            let back = invert_lightness_linear(invert_lightness_linear(rgb));
+            for (x, y) in rgb.iter().zip(back.iter()) {
+                assert!((x - y).abs() < 1e-3, "{:?} came back as {:?}", rgb, back);
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_gamut_is_chroma_clamped() {
+        // inverting saturated blue or red at full chroma leaves sRGB
+        for rgb in [[0.0, 0.0, 1.0], [1.0, 0.0, 0.0]] {
+            let lab = linear_to_oklab(rgb);
+            assert!(!in_gamut(oklab_to_linear([1.0 - lab[0], lab[1], lab[2]])));
+
+            let out = invert_lightness_linear(rgb);
+            assert!(in_gamut(out), "{:?} gave {:?}", rgb, out);
+            let out_lab = linear_to_oklab(out);
+            assert!((out_lab[0] - (1.0 - lab[0])).abs() < 1e-3);
+            assert!((hue(out_lab) - hue(lab)).abs() < 1e-2);
+            assert!(chroma(out_lab) < chroma(lab));
+        }
+    }
+}
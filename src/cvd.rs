@@ -0,0 +1,255 @@
+use crate::filter::{map_pixels, Filter, Frame, FrameInfo};
+
+type Matrix = [[f32; 3]; 3];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Deficiency {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+impl Deficiency {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "protan" | "protanopia" => Ok(Deficiency::Protanopia),
+            "deutan" | "deuteranopia" => Ok(Deficiency::Deuteranopia),
+            "tritan" | "tritanopia" => Ok(Deficiency::Tritanopia),
+            _ => Err(format!(
+                "unknown colour deficiency '{}', expected 'protan', 'deutan' or 'tritan'",
+                s
+            )),
+        }
+    }
+
+    /// Linear RGB simulation matrix of Machado, Oliveira and Fernandes
+    /// (2009) at severity 1.
+    pub fn matrix(&self) -> Matrix {
+        match self {
+            Deficiency::Protanopia => [
+                [0.152_286, 1.052_583, -0.204_868],
+                [0.114_503, 0.786_281, 0.099_216],
+                [-0.003_882, -0.048_116, 1.051_998],
+            ],
+            Deficiency::Deuteranopia => [
+                [0.367_322, 0.860_646, -0.227_968],
+                [0.280_085, 0.672_501, 0.047_413],
+                [-0.011_820, 0.042_940, 0.968_881],
+            ],
+            Deficiency::Tritanopia => [
+                [1.255_528, -0.076_749, -0.178_779],
+                [-0.078_411, 0.930_809, 0.147_602],
+                [0.004_733, 0.691_367, 0.303_900],
+            ],
+        }
+    }
+
+    /// Error redistribution of Fidaner et al. (2005): the detail lost in the
+    /// simulated view is shifted into channels that can still be told apart.
+    /// Red-green deficiencies move it into green and blue, tritanopia into
+    /// red and green.
+    pub fn shift(&self) -> Matrix {
+        match self {
+            Deficiency::Protanopia | Deficiency::Deuteranopia => {
+                [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]]
+            }
+            Deficiency::Tritanopia => [[1.0, 0.0, 0.7], [0.0, 1.0, 0.7], [0.0, 0.0, 0.0]],
+        }
+    }
+}
+
+fn multiply(m: &Matrix, v: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+/// Shows the frame as someone with the deficiency would see it, for checking
+/// that a UI does not rely on colour alone.
+pub struct Simulate(pub Deficiency);
+
+impl Filter for Simulate {
+    fn apply(&mut self, frame: &mut Frame, info: &FrameInfo) {
+        let matrix = self.0.matrix();
+        map_pixels(frame, info, |rgb| {
+            info.from_linear(multiply(&matrix, info.to_linear(rgb)))
+        });
+    }
+}
+
+/// Daltonization: moves colour differences the deficiency hides into ones it
+/// keeps, so colours that would look the same can be told apart.
+pub struct Daltonize(pub Deficiency);
+
+impl Filter for Daltonize {
+    fn apply(&mut self, frame: &mut Frame, info: &FrameInfo) {
+        let matrix = self.0.matrix();
+        let shift = self.0.shift();
+        map_pixels(frame, info, |rgb| {
+            let lin = info.to_linear(rgb);
+            let seen = multiply(&matrix, lin);
+            let moved = multiply(&shift, [0, 1, 2].map(|c| lin[c] - seen[c]));
+            info.from_linear([0, 1, 2].map(|c| (lin[c] + moved[c]).clamp(0.0, 1.0)))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Deficiency; 3] = [
+        Deficiency::Protanopia,
+        Deficiency::Deuteranopia,
+        Deficiency::Tritanopia,
+    ];
+
+    /// Runs sRGB colours through `filter` and returns them as sRGB.
+    fn run(filter: &mut impl Filter, colours: &[[u8; 3]]) -> Vec<[u8; 3]> {
+        let mut frame = Frame::new(colours.len(), 1);
+        for (p, c) in frame.pixels_mut().zip(colours) {
+            p.copy_from_slice(&[c[2], c[1], c[0], 255]);
+        }
+        filter.apply(&mut frame, &FrameInfo::new(0, 0, 0));
+        frame.pixels().map(|p| [p[2], p[1], p[0]]).collect()
+    }
+
+    fn distance(a: [u8; 3], b: [u8; 3]) -> f32 {
+        (0..3)
+            .map(|c| (a[c] as f32 - b[c] as f32).powi(2))
+            .sum::<f32>()
+            .sqrt()
+    }
+
+    fn assert_close(actual: &[[u8; 3]], expected: &[[u8; 3]]) {
+        let close = actual
+            .iter()
+            .zip(expected)
+            .all(|(a, e)| (0..3).all(|c| a[c].abs_diff(e[c]) <= 1));
+        assert!(close, "{:?}, expected {:?}", actual, expected);
+    }
+
+    #[test]
+    fn greys_are_unchanged() {
+        let greys = [[0, 0, 0], [128, 128, 128], [255, 255, 255]];
+        for deficiency in ALL {
+            assert_close(&run(&mut Simulate(deficiency), &greys), &greys);
+            assert_close(&run(&mut Daltonize(deficiency), &greys), &greys);
+        }
+    }
+
+    #[test]
+    fn simulate_matches_reference() {
+        // the sRGB primaries and two mixed colours through the Machado et al.
+        // matrices, worked out in double precision
+        let colours = [
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [200, 60, 60],
+            [60, 160, 120],
+        ];
+        let expected = [
+            (
+                Deficiency::Protanopia,
+                [
+                    [109, 95, 0],
+                    [255, 229, 0],
+                    [0, 89, 255],
+                    [100, 92, 59],
+                    [157, 149, 118],
+                ],
+            ),
+            (
+                Deficiency::Deuteranopia,
+                [
+                    [163, 144, 0],
+                    [239, 214, 58],
+                    [0, 61, 251],
+                    [135, 122, 56],
+                    [143, 139, 123],
+                ],
+            ),
+            (
+                Deficiency::Tritanopia,
+                [
+                    [255, 0, 15],
+                    [0, 247, 217],
+                    [0, 107, 150],
+                    [220, 11, 62],
+                    [0, 160, 149],
+                ],
+            ),
+        ];
+        for (deficiency, expected) in expected {
+            assert_close(&run(&mut Simulate(deficiency), &colours), &expected);
+        }
+    }
+
+    #[test]
+    fn daltonize_matches_reference() {
+        let colours = [
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [200, 60, 60],
+            [60, 160, 120],
+        ];
+        let expected = [
+            (
+                Deficiency::Protanopia,
+                [
+                    [255, 184, 203],
+                    [0, 184, 0],
+                    [0, 59, 255],
+                    [200, 149, 162],
+                    [60, 123, 0],
+                ],
+            ),
+            (
+                Deficiency::Deuteranopia,
+                [
+                    [255, 112, 180],
+                    [0, 221, 0],
+                    [0, 94, 255],
+                    [200, 102, 146],
+                    [60, 145, 36],
+                ],
+            ),
+            (
+                Deficiency::Tritanopia,
+                [
+                    [223, 77, 0],
+                    [0, 201, 0],
+                    [213, 158, 255],
+                    [177, 82, 60],
+                    [33, 143, 120],
+                ],
+            ),
+        ];
+        for (deficiency, expected) in expected {
+            assert_close(&run(&mut Daltonize(deficiency), &colours), &expected);
+        }
+    }
+
+    #[test]
+    fn daltonize_separates_confused_colours() {
+        let pairs = [
+            (Deficiency::Protanopia, [[200, 60, 60], [90, 100, 60]]),
+            (Deficiency::Deuteranopia, [[200, 60, 60], [120, 120, 50]]),
+            (Deficiency::Tritanopia, [[0, 0, 255], [0, 110, 160]]),
+        ];
+        for (deficiency, pair) in pairs {
+            let seen = run(&mut Simulate(deficiency), &pair);
+            let corrected = run(&mut Daltonize(deficiency), &pair);
+            let corrected_seen = run(&mut Simulate(deficiency), &corrected);
+            let before = distance(seen[0], seen[1]);
+            let after = distance(corrected_seen[0], corrected_seen[1]);
+            assert!(
+                after > 3.0 * before,
+                "{:?}: {} -> {}",
+                deficiency,
+                before,
+                after
+            );
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use std::time::{Duration, Instant};
+
+/// Turns a stream of brightness measurements into a filter weight without
+/// strobing when the measurement hovers around a single threshold.
+///
+/// The filter switches on once the value rises above `on` and off once it
+/// drops below `off`. After switching it stays put for at least `min_dwell`.
+/// The returned weight then moves towards the new state over `fade_frames`
+/// updates, so 0 switches instantly.
+#[derive(Clone, Debug)]
+pub struct Controller {
+    pub on: f32,
+    pub off: f32,
+    pub min_dwell: Duration,
+    pub fade_frames: u32,
+    active: bool,
+    pending: bool,
+    switched_at: Option<Instant>,
+    weight: f32,
+}
+
+impl Controller {
+    pub fn new(on: f32, off: f32, min_dwell: Duration, fade_frames: u32) -> Self {
+        Controller {
+            on,
+            off: off.min(on),
+            min_dwell,
+            fade_frames,
+            active: false,
+            pending: false,
+            switched_at: None,
+            weight: 0.0,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// The last measurement crossed a threshold, but the switch waits for
+    /// `min_dwell` to run out. Callers have to keep updating until it does,
+    /// even if the content stops changing.
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Weight is part way through a cross-fade, or a switch is pending.
+    pub fn is_changing(&self) -> bool {
+        self.pending || (self.weight > 0.0 && self.weight < 1.0)
+    }
+
+    /// Current filter weight, from 0 (unfiltered) to 1 (fully filtered).
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    /// Feeds one measurement taken at `now` and returns the new weight.
+    pub fn update(&mut self, value: f32, now: Instant) -> f32 {
+        let settled = match self.switched_at {
+            Some(at) => now.saturating_duration_since(at) >= self.min_dwell,
+            None => true,
+        };
+        let next = if self.active {
+            value >= self.off
+        } else {
+            value > self.on
+        };
+        if settled && next != self.active {
+            self.active = next;
+            self.switched_at = Some(now);
+        }
+        self.pending = next != self.active;
+
+        let target = if self.active { 1.0 } else { 0.0 };
+        if self.fade_frames == 0 {
+            self.weight = target;
+        } else {
+            let step = 1.0 / self.fade_frames as f32;
+            self.weight = if self.weight < target {
+                (self.weight + step).min(target)
+            } else {
+                (self.weight - step).max(target)
+            };
+        }
+        self.weight
+    }
+}
+
+/// Limits how quickly output luminance may rise, so a sudden switch to a
+/// bright page is eased in instead of flashing at full brightness before the
+/// darkening decision catches up. Drops in luminance pass straight through.
+#[derive(Clone, Debug)]
+pub struct SlewLimiter {
+    /// Largest rise in mean relative luminance allowed per frame.
+    pub max_rise: f32,
+    level: Option<f32>,
+    gain: f32,
+}
+
+impl SlewLimiter {
+    pub fn new(max_rise: f32) -> Self {
+        SlewLimiter {
+            max_rise,
+            level: None,
+            gain: 1.0,
+        }
+    }
+
+    /// Feeds the mean luminance the next frame would have and returns the
+    /// gain to scale its linear light by.
+    pub fn update(&mut self, luminance: f32) -> f32 {
+        let allowed = match self.level {
+            Some(level) => luminance.min(level + self.max_rise),
+            None => luminance,
+        };
+        self.level = Some(allowed);
+        self.gain = if luminance > 0.0 {
+            allowed / luminance
+        } else {
+            1.0
+        };
+        self.gain
+    }
+
+    /// Output is still being held below its full brightness.
+    pub fn is_limiting(&self) -> bool {
+        self.gain < 1.0
+    }
+}
+
+/// Holds the mean luminance of the output at `target` by continuously
+/// adjusting a dimming gain, instead of switching a filter on and off. Content
+/// already darker than the target is left alone.
+///
+/// The gain moves a fraction `response` of the way towards the target on
+/// every update, measured in log space, so for a `response` between 0 and 1
+/// it approaches without overshooting. It never leaves `min_gain..=1`.
+#[derive(Clone, Debug)]
+pub struct TargetDimmer {
+    pub target: f32,
+    pub min_gain: f32,
+    pub response: f32,
+    gain: f32,
+    ideal: f32,
+}
+
+impl TargetDimmer {
+    pub fn new(target: f32) -> Self {
+        TargetDimmer {
+            target,
+            min_gain: 0.05,
+            response: 0.3,
+            gain: 1.0,
+            ideal: 1.0,
+        }
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// Feeds the mean luminance of the undimmed frame and returns the gain to
+    /// scale its linear light by.
+    pub fn update(&mut self, luminance: f32) -> f32 {
+        let output = self.gain * luminance;
+        if output > 0.0 {
+            let error = self.target.ln() - output.ln();
+            self.gain *= (self.response * error).exp();
+        }
+        self.gain = self.gain.clamp(self.min_gain, 1.0);
+        // black frames give nothing to aim for, so the gain is kept for
+        // when content comes back and counts as settled
+        self.ideal = if luminance > 0.0 {
+            (self.target / luminance).clamp(self.min_gain, 1.0)
+        } else {
+            self.gain
+        };
+        self.gain
+    }
+
+    /// The gain is still moving towards its target.
+    pub fn is_settling(&self) -> bool {
+        (self.gain - self.ideal).abs() > 0.005
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn controller_hysteresis() {
+        let now = Instant::now();
+        let mut c = Controller::new(0.5, 0.4, Duration::ZERO, 0);
+        let weights = [0.45, 0.55, 0.45, 0.41, 0.35, 0.45, 0.5, 0.51]
+            .iter()
+            .map(|&v| c.update(v, now))
+            .collect::<Vec<_>>();
+        assert_eq!(weights, [0.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn controller_waits_for_dwell() {
+        let start = Instant::now();
+        let mut c = Controller::new(0.5, 0.4, ms(500), 0);
+        assert_eq!(c.update(0.9, start), 1.0);
+        assert!(!c.is_pending());
+
+        // flickering back and forth inside the dwell time is ignored
+        assert_eq!(c.update(0.1, start + ms(100)), 1.0);
+        assert!(c.is_pending() && c.is_changing());
+        assert_eq!(c.update(0.9, start + ms(200)), 1.0);
+        assert!(!c.is_pending());
+
+        // a crossing during the dwell still switches once it runs out, as
+        // long as the caller keeps updating with the same value
+        assert_eq!(c.update(0.1, start + ms(300)), 1.0);
+        assert!(c.is_pending());
+        assert_eq!(c.update(0.1, start + ms(499)), 1.0);
+        assert_eq!(c.update(0.1, start + ms(500)), 0.0);
+        assert!(!c.is_pending() && !c.is_changing());
+    }
+
+    #[test]
+    fn controller_fades() {
+        let now = Instant::now();
+        let mut c = Controller::new(0.5, 0.4, Duration::ZERO, 4);
+        let up = (0..5).map(|_| c.update(0.9, now)).collect::<Vec<_>>();
+        assert_eq!(up, [0.25, 0.5, 0.75, 1.0, 1.0]);
+        assert!(!c.is_changing());
+
+        assert_eq!(c.update(0.1, now), 0.75);
+        assert!(c.is_changing() && !c.is_pending());
+        // turning back part way reverses the fade from where it is
+        assert_eq!(c.update(0.9, now), 1.0);
+    }
+
+    #[test]
+    fn slew_limiter_eases_in_steps_up() {
+        let mut limiter = SlewLimiter::new(0.1);
+        assert_eq!(limiter.update(0.1), 1.0);
+        let mut output = vec![];
+        for _ in 0..10 {
+            let gain = limiter.update(0.9);
+            output.push(gain * 0.9);
+        }
+        let expected = [0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9, 0.9, 0.9];
+        for (out, exp) in output.iter().zip(expected) {
+            assert!((out - exp).abs() < 1e-5, "{:?}", output);
+        }
+        assert!(!limiter.is_limiting());
+    }
+
+    #[test]
+    fn slew_limiter_passes_steps_down() {
+        let mut limiter = SlewLimiter::new(0.1);
+        limiter.update(0.9);
+        assert_eq!(limiter.update(0.05), 1.0);
+        assert!(!limiter.is_limiting());
+        // and rises again from the new level
+        assert!((limiter.update(0.9) * 0.9 - 0.15).abs() < 1e-5);
+        assert!(limiter.is_limiting());
+    }
+
+    #[test]
+    fn slew_limiter_handles_black() {
+        let mut limiter = SlewLimiter::new(0.1);
+        assert_eq!(limiter.update(0.0), 1.0);
+        assert!((limiter.update(1.0) - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn target_dimmer_steps_without_overshoot() {
+        let mut dimmer = TargetDimmer::new(0.2);
+        let steps = [(0.9, 30), (0.1, 30), (0.6, 30), (0.2, 30), (1.0, 30)];
+        for (luminance, frames) in steps {
+            let ideal = (0.2f32 / luminance).clamp(dimmer.min_gain, 1.0);
+            let mut last = dimmer.gain();
+            for _ in 0..frames {
+                let gain = dimmer.update(luminance);
+                assert!((dimmer.min_gain..=1.0).contains(&gain));
+                // moves straight towards the ideal gain without passing it
+                if last >= ideal {
+                    assert!(gain <= last && gain >= ideal - 1e-6, "{} -> {}", last, gain);
+                } else {
+                    assert!(gain >= last && gain <= ideal + 1e-6, "{} -> {}", last, gain);
+                }
+                last = gain;
+            }
+            assert!(
+                (last - ideal).abs() < 0.01,
+                "{} settled at {}",
+                luminance,
+                last
+            );
+            assert!(!dimmer.is_settling());
+        }
+    }
+
+    #[test]
+    fn target_dimmer_gain_stays_bounded() {
+        let mut dimmer = TargetDimmer::new(0.01);
+        for _ in 0..100 {
+            assert!(dimmer.update(1.0) >= dimmer.min_gain);
+        }
+        assert_eq!(dimmer.gain(), dimmer.min_gain);
+        assert!(!dimmer.is_settling());
+        let gain = dimmer.update(0.0);
+        assert!(!dimmer.is_settling());
+        for _ in 0..100 {
+            dimmer.update(0.0);
+        }
+        assert_eq!(dimmer.gain(), gain);
+        assert!(!dimmer.is_settling());
+
+        // nor does a black frame part way through settling keep it busy
+        let mut dimmer = TargetDimmer::new(0.2);
+        dimmer.update(0.9);
+        assert!(dimmer.is_settling());
+        dimmer.update(0.0);
+        assert!(!dimmer.is_settling());
+        assert_eq!(TargetDimmer::new(0.5).update(0.2), 1.0);
+    }
+}
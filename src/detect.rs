@@ -0,0 +1,176 @@
+use crate::filter::Frame;
+use crate::mask::Mask;
+
+/// Measurements of one block of a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BlockFeatures {
+    /// Mean standard deviation of the R, G and B channels, from 0 to 1.
+    pub colour_spread: f32,
+    /// Mean HSV saturation.
+    pub saturation: f32,
+    /// Fraction of neighbouring pixels that differ slightly, as in the smooth
+    /// gradients of photos and video.
+    pub gradients: f32,
+    /// Fraction of neighbouring pixels that differ sharply, as at text and
+    /// UI edges.
+    pub edges: f32,
+}
+
+impl BlockFeatures {
+    pub fn measure(frame: &Frame, x0: usize, y0: usize, x1: usize, y1: usize) -> Self {
+        let mut sum = [0.0f32; 3];
+        let mut sum_sq = [0.0f32; 3];
+        let mut saturation = 0.0;
+        let mut gradients = 0;
+        let mut edges = 0;
+        let mut pairs = 0;
+        for y in y0..y1 {
+            let row = &frame.data[(y * frame.width + x0) * 4..(y * frame.width + x1) * 4];
+            let mut prev_luma: Option<i32> = None;
+            for p in row.chunks_exact(4) {
+                let rgb = [p[2], p[1], p[0]].map(|c| c as f32 / 255.0);
+                for c in 0..3 {
+                    sum[c] += rgb[c];
+                    sum_sq[c] += rgb[c] * rgb[c];
+                }
+                let max = rgb[0].max(rgb[1]).max(rgb[2]);
+                let min = rgb[0].min(rgb[1]).min(rgb[2]);
+                if max > 0.0 {
+                    saturation += (max - min) / max;
+                }
+                let luma = (54 * p[2] as i32 + 183 * p[1] as i32 + 19 * p[0] as i32) >> 8;
+                if let Some(prev) = prev_luma {
+                    let diff = (luma - prev).abs();
+                    if diff >= 48 {
+                        edges += 1;
+                    } else if diff > 1 {
+                        gradients += 1;
+                    }
+                    pairs += 1;
+                }
+                prev_luma = Some(luma);
+            }
+        }
+        let n = ((x1 - x0) * (y1 - y0)).max(1) as f32;
+        let pairs = pairs.max(1) as f32;
+        let colour_spread = (0..3)
+            .map(|c| (sum_sq[c] / n - (sum[c] / n).powi(2)).max(0.0).sqrt())
+            .sum::<f32>()
+            / 3.0;
+        BlockFeatures {
+            colour_spread,
+            saturation: saturation / n,
+            gradients: gradients as f32 / pairs,
+            edges: edges as f32 / pairs,
+        }
+    }
+}
+
+/// Finds photographic regions, such as pictures and video tiles, so they can
+/// be left uninverted. The resulting mask is 0 over photos and 1 elsewhere.
+///
+/// Each block's score is smoothed over frames and switches with hysteresis,
+/// so a region does not flicker between detected and not detected.
+pub struct PhotoDetector {
+    pub block_size: usize,
+    /// Blocks flatter than this are never photos.
+    pub min_colour_spread: f32,
+    /// Fraction of gradient pairs above which a block looks photographic.
+    pub min_gradients: f32,
+    /// Fraction of edge pairs above which a block looks like text.
+    pub max_edges: f32,
+    /// Weight of each new frame in the smoothed score.
+    pub smoothing: f32,
+    scores: Vec<f32>,
+    mask: Mask,
+}
+
+const ON: f32 = 0.6;
+const OFF: f32 = 0.4;
+
+impl PhotoDetector {
+    pub fn new(block_size: usize) -> Self {
+        PhotoDetector {
+            block_size: block_size.max(2),
+            min_colour_spread: 0.03,
+            min_gradients: 0.35,
+            max_edges: 0.2,
+            smoothing: 0.3,
+            scores: vec![],
+            mask: Mask::default(),
+        }
+    }
+
+    /// Score from 0 to 1 of how photographic a block looks.
+    pub fn score(&self, f: &BlockFeatures) -> f32 {
+        if f.colour_spread < self.min_colour_spread || f.edges > self.max_edges {
+            return 0.0;
+        }
+        // saturated content needs less texture to count as a photo
+        let needed = self.min_gradients * (1.0 - f.saturation.min(0.5));
+        if f.gradients >= needed {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    pub fn detect(&mut self, frame: &Frame) -> &Mask {
+        let mask = Mask::new(frame.width, frame.height, self.block_size, 1.0);
+        if mask.cols != self.mask.cols || mask.rows != self.mask.rows {
+            self.scores = vec![0.0; mask.cols * mask.rows];
+            self.mask = mask;
+        }
+        let size = self.block_size;
+        for row in 0..self.mask.rows {
+            for col in 0..self.mask.cols {
+                let (x0, y0) = (col * size, row * size);
+                let (x1, y1) = ((x0 + size).min(frame.width), (y0 + size).min(frame.height));
+                let features = BlockFeatures::measure(frame, x0, y0, x1, y1);
+                let i = row * self.mask.cols + col;
+                self.scores[i] += (self.score(&features) - self.scores[i]) * self.smoothing;
+
+                let photo = self.mask.get(col, row) == 0.0;
+                if !photo && self.scores[i] > ON {
+                    self.mask.set(col, row, 0.0);
+                } else if photo && self.scores[i] < OFF {
+                    self.mask.set(col, row, 1.0);
+                }
+            }
+        }
+        self.fill_holes();
+        &self.mask
+    }
+
+    /// Some block's score has not yet settled, so the mask may still change
+    /// even if the frame does not.
+    pub fn is_settling(&self) -> bool {
+        self.scores.iter().any(|&s| s > 0.01 && s < 0.99)
+    }
+
+    /// Marks blocks surrounded by photo on both sides as photo too, so smooth
+    /// areas inside a picture, such as sky, are not inverted on their own.
+    fn fill_holes(&mut self) {
+        let (cols, rows) = (self.mask.cols, self.mask.rows);
+        let is_photo = |m: &Mask, c: usize, r: usize| m.get(c, r) == 0.0;
+        let snapshot = self.mask.clone();
+        for row in 0..rows {
+            for col in 0..cols {
+                if is_photo(&snapshot, col, row) {
+                    continue;
+                }
+                let horizontal = col > 0
+                    && col + 1 < cols
+                    && is_photo(&snapshot, col - 1, row)
+                    && is_photo(&snapshot, col + 1, row);
+                let vertical = row > 0
+                    && row + 1 < rows
+                    && is_photo(&snapshot, col, row - 1)
+                    && is_photo(&snapshot, col, row + 1);
+                if horizontal || vertical {
+                    self.mask.set(col, row, 0.0);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::OnceLock;
+
+const BAYER_SIZE: usize = 8;
+const NOISE_SIZE: usize = 32;
+
+/// Pattern used to spread rounding to 8 bits over neighbouring pixels, so
+/// smooth gradients do not band after heavy dimming. Patterns are tied to
+/// screen position and never change over time, so static content stays
+/// static.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dither {
+    /// 8x8 ordered Bayer matrix.
+    Bayer,
+    /// 32x32 blue noise tile made with the void-and-cluster method.
+    BlueNoise,
+}
+
+impl Dither {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "bayer" => Ok(Dither::Bayer),
+            "blue-noise" => Ok(Dither::BlueNoise),
+            _ => Err(format!(
+                "unknown dither '{}', expected 'bayer' or 'blue-noise'",
+                s
+            )),
+        }
+    }
+
+    /// Threshold from 0 to 1 at screen position (`x`, `y`).
+    pub fn threshold(&self, x: i32, y: i32) -> f32 {
+        match self {
+            Dither::Bayer => {
+                let (x, y) = (x.rem_euclid(8) as usize, y.rem_euclid(8) as usize);
+                bayer()[y * BAYER_SIZE + x]
+            }
+            Dither::BlueNoise => {
+                let n = NOISE_SIZE as i32;
+                let (x, y) = (x.rem_euclid(n) as usize, y.rem_euclid(n) as usize);
+                blue_noise()[y * NOISE_SIZE + x]
+            }
+        }
+    }
+}
+
+fn bayer() -> &'static [f32; BAYER_SIZE * BAYER_SIZE] {
+    static TABLE: OnceLock<[f32; BAYER_SIZE * BAYER_SIZE]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0.0; BAYER_SIZE * BAYER_SIZE];
+        for y in 0..BAYER_SIZE {
+            for x in 0..BAYER_SIZE {
+                // interleave the bits of x ^ y and y in reverse order
+                let (a, b) = (x ^ y, y);
+                let mut rank = 0;
+                for bit in 0..3 {
+                    rank = (rank << 2) | (((a >> bit) & 1) << 1) | ((b >> bit) & 1);
+                }
+                table[y * BAYER_SIZE + x] = (rank as f32 + 0.5) / 64.0;
+            }
+        }
+        table
+    })
+}
+
+fn blue_noise() -> &'static [f32; NOISE_SIZE * NOISE_SIZE] {
+    static TABLE: OnceLock<[f32; NOISE_SIZE * NOISE_SIZE]> = OnceLock::new();
+    TABLE.get_or_init(void_and_cluster)
+}
+
+/// Ulichney's void-and-cluster method: points are ranked by repeatedly
+/// removing the one in the tightest cluster and adding one in the largest
+/// void, measured by a Gaussian energy that wraps around the tile edges.
+fn void_and_cluster() -> [f32; NOISE_SIZE * NOISE_SIZE] {
+    const N: usize = NOISE_SIZE * NOISE_SIZE;
+    const SIGMA: f32 = 1.5;
+    let mut kernel = [0.0f32; N];
+    for (i, k) in kernel.iter_mut().enumerate() {
+        let wrap = |d: usize| d.min(NOISE_SIZE - d) as f32;
+        let (dx, dy) = (wrap(i % NOISE_SIZE), wrap(i / NOISE_SIZE));
+        *k = (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp();
+    }
+    let offset = |from: usize, to: usize| {
+        let dx = (to % NOISE_SIZE + NOISE_SIZE - from % NOISE_SIZE) % NOISE_SIZE;
+        let dy = (to / NOISE_SIZE + NOISE_SIZE - from / NOISE_SIZE) % NOISE_SIZE;
+        dy * NOISE_SIZE + dx
+    };
+    let splat = |energy: &mut [f32; N], at: usize, sign: f32| {
+        for (i, e) in energy.iter_mut().enumerate() {
+            *e += sign * kernel[offset(at, i)];
+        }
+    };
+    let tightest = |energy: &[f32; N], set: &[bool; N]| {
+        (0..N)
+            .filter(|&i| set[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let loosest = |energy: &[f32; N], set: &[bool; N]| {
+        (0..N)
+            .filter(|&i| !set[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // a fixed seed keeps the pattern the same on every run
+    let mut seed = 0x2545_f491u32;
+    let mut initial = [false; N];
+    let mut energy = [0.0f32; N];
+    let mut count = 0;
+    while count < N / 10 {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let i = seed as usize % N;
+        if !initial[i] {
+            initial[i] = true;
+            splat(&mut energy, i, 1.0);
+            count += 1;
+        }
+    }
+    // spread the initial points out evenly
+    for _ in 0..N {
+        let cluster = tightest(&energy, &initial);
+        initial[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let void = loosest(&energy, &initial);
+        initial[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = [0usize; N];
+    let mut set = initial;
+    let mut e = energy;
+    for r in (0..count).rev() {
+        let cluster = tightest(&e, &set);
+        set[cluster] = false;
+        splat(&mut e, cluster, -1.0);
+        rank[cluster] = r;
+    }
+    let mut set = initial;
+    let mut e = energy;
+    for r in count..N {
+        let void = loosest(&e, &set);
+        set[void] = true;
+        splat(&mut e, void, 1.0);
+        rank[void] = r;
+    }
+    rank.map(|r| (r as f32 + 0.5) / N as f32)
+}
@@ -0,0 +1,540 @@
+use std::time::Instant;
+
+use crate::color;
+use crate::cvd::{Daltonize, Deficiency, Simulate};
+use crate::dither::Dither;
+use crate::fringe::FringeSuppress;
+use crate::lut::{Interpolation, Lut};
+use crate::mask::Mask;
+use crate::palette::{PaletteRemap, Theme};
+use crate::sharpen::Sharpen;
+use crate::temperature::Temperature;
+use crate::threshold::Threshold;
+
+/// A BGRA image of the region covered by the window.
+#[derive(Clone, Default)]
+pub struct Frame {
+    pub data: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Frame {
+            data: vec![0; width * height * 4],
+            width,
+            height,
+        }
+    }
+
+    /// Copies the `width` x `height` region at (`x`, `y`) out of a BGRA
+    /// screenshot `src_width` pixels wide. Pixels outside the screenshot
+    /// repeat its nearest edge.
+    pub fn copy_region(
+        &mut self,
+        src: &[u8],
+        src_width: usize,
+        x: i32,
+        y: i32,
+        width: usize,
+        height: usize,
+    ) {
+        self.width = width;
+        self.height = height;
+        self.data.resize(width * height * 4, 0);
+        let src_height = if src_width == 0 {
+            0
+        } else {
+            src.len() / (src_width * 4)
+        };
+        if src_height == 0 {
+            return;
+        }
+        // columns that are on screen can be copied in one go
+        let first = (-x).clamp(0, width as i32) as usize;
+        let end = (src_width as i32 - x).clamp(first as i32, width as i32) as usize;
+        for row in 0..height {
+            let src_row = (row as i32 + y).clamp(0, src_height as i32 - 1) as usize;
+            let src_row = &src[src_row * src_width * 4..(src_row + 1) * src_width * 4];
+            let dst_row = &mut self.data[row * width * 4..(row + 1) * width * 4];
+            if first < end {
+                let s = (first as i32 + x) as usize * 4;
+                dst_row[first * 4..end * 4].copy_from_slice(&src_row[s..s + (end - first) * 4]);
+            }
+            for col in (0..first).chain(end..width) {
+                let s = (col as i32 + x).clamp(0, src_width as i32 - 1) as usize * 4;
+                dst_row[col * 4..col * 4 + 4].copy_from_slice(&src_row[s..s + 4]);
+            }
+        }
+    }
+
+    /// Blends towards `original`, keeping `amount` of this frame. Both frames
+    /// must be the same size.
+    pub fn mix(&mut self, original: &Frame, amount: f32) {
+        let amount = amount.clamp(0.0, 1.0);
+        for (out, orig) in self.data.iter_mut().zip(original.data.iter()) {
+            *out = (*orig as f32 + (*out as f32 - *orig as f32) * amount).round() as u8;
+        }
+    }
+
+    /// Like [`Frame::mix`], but with an amount that varies across the frame.
+    /// The amount at each pixel is the product of all `masks`.
+    pub fn mix_masked(&mut self, original: &Frame, masks: &[&Mask]) {
+        if let Some(amount) = masks.iter().map(|m| m.uniform()).product::<Option<f32>>() {
+            return self.mix(original, amount);
+        }
+        let width = self.width;
+        for (i, (out, orig)) in self
+            .data
+            .chunks_exact_mut(4)
+            .zip(original.data.chunks_exact(4))
+            .enumerate()
+        {
+            let amount = masks
+                .iter()
+                .map(|m| m.sample(i % width, i / width))
+                .product::<f32>()
+                .clamp(0.0, 1.0);
+            for c in 0..3 {
+                out[c] = (orig[c] as f32 + (out[c] as f32 - orig[c] as f32) * amount).round() as u8;
+            }
+        }
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = &[u8]> {
+        self.data.chunks_exact(4)
+    }
+
+    pub fn pixels_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+        self.data.chunks_exact_mut(4)
+    }
+}
+
+/// Information about the frame being filtered.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameInfo {
+    /// Screen position of the top-left pixel of the frame.
+    pub x: i32,
+    pub y: i32,
+    /// Counts up by one for every frame passed to the pipeline.
+    pub index: u64,
+    pub time: Instant,
+    /// Filters see linear light rather than sRGB encoded values. Set by the
+    /// [`Pipeline`] running the filter.
+    pub linear: bool,
+    /// Pattern for [`map_pixels`] to dither with when rounding to 8 bits.
+    /// Set by the [`Pipeline`] for its last filter, and by the render loop
+    /// for the final dimming. Filters that write exact bytes, such as sRGB
+    /// [`Invert`] or [`Threshold`], have no fraction to dither.
+    pub dither: Option<Dither>,
+}
+
+impl FrameInfo {
+    pub fn new(x: i32, y: i32, index: u64) -> Self {
+        FrameInfo {
+            x,
+            y,
+            index,
+            time: Instant::now(),
+            linear: false,
+            dither: None,
+        }
+    }
+
+    /// Converts a value seen by a [`map_pixels`] callback to linear light.
+    pub fn to_linear(&self, rgb: [f32; 3]) -> [f32; 3] {
+        if self.linear {
+            rgb
+        } else {
+            rgb.map(color::srgb_to_linear)
+        }
+    }
+
+    /// Converts linear light back to what a [`map_pixels`] callback returns.
+    pub fn from_linear(&self, rgb: [f32; 3]) -> [f32; 3] {
+        if self.linear {
+            rgb
+        } else {
+            rgb.map(color::linear_to_srgb)
+        }
+    }
+
+    /// Converts a value seen by a [`map_pixels`] callback to sRGB.
+    pub fn to_srgb(&self, rgb: [f32; 3]) -> [f32; 3] {
+        if self.linear {
+            rgb.map(color::linear_to_srgb)
+        } else {
+            rgb
+        }
+    }
+
+    /// Converts sRGB back to what a [`map_pixels`] callback returns.
+    pub fn from_srgb(&self, rgb: [f32; 3]) -> [f32; 3] {
+        if self.linear {
+            rgb.map(color::srgb_to_linear)
+        } else {
+            rgb
+        }
+    }
+}
+
+pub trait Filter: Send {
+    fn apply(&mut self, frame: &mut Frame, info: &FrameInfo);
+}
+
+/// Runs `f` over every pixel as normalized RGB, in linear light if
+/// `info.linear` is set and sRGB otherwise. Runs of identical pixels, which
+/// are common in application windows, are only computed once.
+pub fn map_pixels<F>(frame: &mut Frame, info: &FrameInfo, mut f: F)
+where
+    F: FnMut([f32; 3]) -> [f32; 3],
+{
+    let decode: fn(u8) -> f32 = if info.linear {
+        color::decode
+    } else {
+        |c| c as f32 / 255.0
+    };
+    let encode: fn(f32) -> f32 = if info.linear {
+        color::encode_unit
+    } else {
+        |c| c.clamp(0.0, 1.0)
+    };
+    let width = frame.width.max(1);
+    let mut last_in = None;
+    let mut last_out = [0.0; 3];
+    for (i, pixel) in frame.pixels_mut().enumerate() {
+        let bgr = [pixel[0], pixel[1], pixel[2]];
+        if last_in != Some(bgr) {
+            last_in = Some(bgr);
+            let out = f([bgr[2], bgr[1], bgr[0]].map(decode)).map(|c| encode(c) * 255.0);
+            last_out = [out[2], out[1], out[0]];
+        }
+        let offset = match info.dither {
+            Some(dither) => {
+                let (x, y) = ((i % width) as i32, (i / width) as i32);
+                dither.threshold(info.x + x, info.y + y) - 0.5
+            }
+            None => 0.0,
+        };
+        for c in 0..3 {
+            pixel[c] = (last_out[c] + offset).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Filters applied one after the other, in the order they were added.
+#[derive(Default)]
+pub struct Pipeline {
+    filters: Vec<Box<dyn Filter>>,
+    linear: bool,
+    dither: Option<Dither>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with<F: Filter + 'static>(mut self, filter: F) -> Self {
+        self.push(filter);
+        self
+    }
+
+    pub fn push<F: Filter + 'static>(&mut self, filter: F) {
+        self.filters.push(Box::new(filter));
+    }
+
+    /// Runs the filters on linear light instead of sRGB encoded values.
+    /// Dimming and blending then behave physically, so midtones come out
+    /// right.
+    pub fn linear(mut self, linear: bool) -> Self {
+        self.linear = linear;
+        self
+    }
+
+    pub fn is_linear(&self) -> bool {
+        self.linear
+    }
+
+    /// Dithers the final rounding to 8 bits, which hides the banding that
+    /// strong dimming or gamma curves cause in gradients. Only the last
+    /// filter dithers, so noise does not build up from stage to stage.
+    pub fn dither(mut self, dither: Option<Dither>) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// Builds a pipeline from a comma separated list such as
+    /// `invert,brightness:0.8,gamma:1.2`, `palette:nord`, `temperature:3400`,
+    /// `invert,fringe`, `invert,sharpen:0.8/1.5`, `threshold:#ffd33d/#000000`,
+    /// `daltonize:deutan,invert` or `lut:looks/soft.cube`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut pipeline = Pipeline::new();
+        for stage in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (name, arg) = match stage.split_once(':') {
+                Some((name, arg)) => (name, Some(arg)),
+                None => (stage, None),
+            };
+            let number = || -> Result<f32, String> {
+                let arg = arg.ok_or_else(|| format!("filter '{}' needs a value", name))?;
+                arg.parse::<f32>()
+                    .map_err(|_| format!("invalid value '{}' for filter '{}'", arg, name))
+            };
+            match name {
+                "invert" => pipeline.push(Invert),
+                "lightness-invert" => pipeline.push(LightnessInvert),
+                "brightness" => pipeline.push(Brightness(number()?)),
+                "gamma" => pipeline.push(Gamma(number()?)),
+                "contrast" => pipeline.push(Contrast(number()?)),
+                "temperature" => pipeline.push(Temperature::new(number()?)),
+                "simulate" | "daltonize" => {
+                    let arg = arg.ok_or_else(|| format!("filter '{}' needs a deficiency", name))?;
+                    let deficiency = Deficiency::parse(arg)?;
+                    if name == "simulate" {
+                        pipeline.push(Simulate(deficiency));
+                    } else {
+                        pipeline.push(Daltonize(deficiency));
+                    }
+                }
+                "threshold" => pipeline.push(match arg {
+                    Some(colours) => Threshold::with_colours(colours)?,
+                    None => Threshold::new(),
+                }),
+                "sharpen" => pipeline.push(match arg {
+                    Some(spec) => Sharpen::parse(spec)?,
+                    None => Sharpen::default(),
+                }),
+                "fringe" => {
+                    let strength = arg.map(|_| number()).transpose()?.unwrap_or(1.0);
+                    pipeline.push(FringeSuppress::new(strength));
+                }
+                "lut" | "lut-tetrahedral" => {
+                    let path = arg.ok_or_else(|| format!("filter '{}' needs a file", name))?;
+                    let lut = Lut::load(path)
+                        .map_err(|e| format!("could not load LUT '{}': {}", path, e))?;
+                    pipeline.push(lut.with_interpolation(if name == "lut" {
+                        Interpolation::Trilinear
+                    } else {
+                        Interpolation::Tetrahedral
+                    }));
+                }
+                "palette" => {
+                    let name = arg.unwrap_or("dark");
+                    let theme = Theme::named(name).ok_or_else(|| {
+                        let names = Theme::names().collect::<Vec<_>>().join(", ");
+                        format!("unknown theme '{}', expected one of: {}", name, names)
+                    })?;
+                    pipeline.push(PaletteRemap::new(theme));
+                }
+                _ => return Err(format!("unknown filter '{}'", name)),
+            }
+        }
+        Ok(pipeline)
+    }
+}
+
+impl Filter for Pipeline {
+    fn apply(&mut self, frame: &mut Frame, info: &FrameInfo) {
+        let last = self.filters.len().saturating_sub(1);
+        for (i, filter) in self.filters.iter_mut().enumerate() {
+            let info = FrameInfo {
+                linear: self.linear,
+                dither: if i == last { self.dither } else { None },
+                ..*info
+            };
+            filter.apply(frame, &info);
+        }
+    }
+}
+
+/// Inverts each channel on its own. Dark becomes light but hues flip.
+pub struct Invert;
+
+impl Filter for Invert {
+    fn apply(&mut self, frame: &mut Frame, info: &FrameInfo) {
+        if info.linear {
+            return map_pixels(frame, info, |rgb| rgb.map(|c| 1.0 - c));
+        }
+        for pixel in frame.pixels_mut() {
+            pixel[0] = 255 - pixel[0];
+            pixel[1] = 255 - pixel[1];
+            pixel[2] = 255 - pixel[2];
+        }
+    }
+}
+
+/// Inverts lightness only, so red stays red.
+pub struct LightnessInvert;
+
+impl Filter for LightnessInvert {
+    fn apply(&mut self, frame: &mut Frame, info: &FrameInfo) {
+        map_pixels(frame, info, |rgb| {
+            info.from_linear(color::invert_lightness_linear(info.to_linear(rgb)))
+        });
+    }
+}
+
+/// Scales every channel by a constant factor.
+pub struct Brightness(pub f32);
+
+impl Filter for Brightness {
+    fn apply(&mut self, frame: &mut Frame, info: &FrameInfo) {
+        let scale = self.0;
+        map_pixels(frame, info, |rgb| rgb.map(|c| c * scale));
+    }
+}
+
+/// Raises every channel to a power. Values above 1 darken midtones.
+pub struct Gamma(pub f32);
+
+impl Filter for Gamma {
+    fn apply(&mut self, frame: &mut Frame, info: &FrameInfo) {
+        let gamma = self.0;
+        map_pixels(frame, info, |rgb| rgb.map(|c| c.powf(gamma)));
+    }
+}
+
+/// Pulls every channel towards mid grey. 1 leaves the frame unchanged and 0
+/// turns it flat grey.
+pub struct Contrast(pub f32);
+
+impl Filter for Contrast {
+    fn apply(&mut self, frame: &mut Frame, info: &FrameInfo) {
+        let amount = self.0;
+        map_pixels(frame, info, |rgb| rgb.map(|c| 0.5 + (c - 0.5) * amount));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Red, white and mid grey over saturated blue, dark red and black, as
+    /// BGRA.
+    const SWATCHES: [u8; 24] = [
+        0, 0, 255, 255, 255, 255, 255, 255, 128, 128, 128, 255, //
+        255, 0, 0, 255, 0, 0, 200, 255, 0, 0, 0, 255,
+    ];
+
+    fn swatches() -> Frame {
+        Frame {
+            data: SWATCHES.to_vec(),
+            width: 3,
+            height: 2,
+        }
+    }
+
+    #[test]
+    fn lightness_invert_golden() {
+        let expected = [
+            0, 0, 126, 255, 0, 0, 0, 255, 72, 72, 72, 255, //
+            255, 91, 31, 255, 0, 0, 177, 255, 255, 255, 255, 255,
+        ];
+        for linear in [false, true] {
+            let mut frame = swatches();
+            let info = FrameInfo {
+                linear,
+                ..FrameInfo::new(0, 0, 0)
+            };
+            LightnessInvert.apply(&mut frame, &info);
+            assert_eq!(frame.data, expected, "linear: {}", linear);
+        }
+    }
+
+    #[test]
+    fn lightness_invert_keeps_hue() {
+        let mut frame = swatches();
+        LightnessInvert.apply(&mut frame, &FrameInfo::new(0, 0, 0));
+        let px = |i: usize| &frame.data[i * 4..i * 4 + 3];
+        // red stays red rather than turning cyan
+        assert!(px(0)[2] > 100 && px(0)[0] == 0 && px(0)[1] == 0);
+        assert_eq!(px(1), [0, 0, 0]);
+        assert_eq!(px(5), [255, 255, 255]);
+        // grey stays grey and moves to the other side of the middle
+        assert!(px(2)[0] == px(2)[1] && px(2)[1] == px(2)[2] && px(2)[0] < 128);
+        // dark blue becomes light blue
+        assert!(px(3)[0] == 255 && px(3)[2] < px(3)[1]);
+    }
+
+    #[test]
+    fn invert_flips_hue() {
+        let mut frame = swatches();
+        Invert.apply(&mut frame, &FrameInfo::new(0, 0, 0));
+        assert_eq!(&frame.data[..4], [255, 255, 0, 255]);
+    }
+
+    /// A smooth horizontal grey ramp, as it might appear in a slide background.
+    fn ramp() -> Frame {
+        let mut frame = Frame::new(64, 16);
+        for (i, pixel) in frame.pixels_mut().enumerate() {
+            let v = 96 + (i % 64) as u8 / 4;
+            pixel.copy_from_slice(&[v, v, v, 255]);
+        }
+        frame
+    }
+
+    fn dim(frame: &mut Frame, dither: Option<Dither>) {
+        let info = FrameInfo {
+            linear: true,
+            dither,
+            ..FrameInfo::new(100, 40, 0)
+        };
+        Brightness(0.05).apply(frame, &info);
+    }
+
+    #[test]
+    fn dither_is_stable() {
+        for dither in [Dither::Bayer, Dither::BlueNoise] {
+            let mut first = ramp();
+            dim(&mut first, Some(dither));
+            for index in 1..4 {
+                let mut frame = ramp();
+                let info = FrameInfo {
+                    linear: true,
+                    dither: Some(dither),
+                    ..FrameInfo::new(100, 40, index)
+                };
+                Brightness(0.05).apply(&mut frame, &info);
+                assert_eq!(frame.data, first.data, "{:?}", dither);
+            }
+
+            let mut pipeline = Pipeline::parse("gamma:2.2").unwrap().dither(Some(dither));
+            let (mut a, mut b) = (ramp(), ramp());
+            pipeline.apply(&mut a, &FrameInfo::new(0, 0, 0));
+            pipeline.apply(&mut b, &FrameInfo::new(0, 0, 1));
+            assert_eq!(a.data, b.data);
+        }
+    }
+
+    #[test]
+    fn dither_keeps_average_level() {
+        // heavy dimming leaves only a few levels, which band without dither
+        let target = |v: u8| color::encode_unit(color::decode(v) * 0.05) * 255.0;
+        let mut plain = ramp();
+        let mut dithered = ramp();
+        dim(&mut plain, None);
+        dim(&mut dithered, Some(Dither::Bayer));
+        // compare the average of each 8x8 tile, which is what the eye sees
+        let tile_error = |frame: &Frame, tx: usize| {
+            let (mut out, mut want) = (0.0, 0.0);
+            for y in 0..8 {
+                for x in tx * 8..tx * 8 + 8 {
+                    out += frame.data[(y * 64 + x) * 4] as f32;
+                    want += target(ramp().data[x * 4]);
+                }
+            }
+            (out - want).abs() / 64.0
+        };
+        let error = |frame: &Frame| (0..8).map(|tx| tile_error(frame, tx)).sum::<f32>();
+        assert!(error(&dithered) < error(&plain) / 2.0);
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::analysis::pixel_luminance;
+use crate::color;
+use crate::filter::{Filter, Frame, FrameInfo};
+
+/// Cells per side of the grid the frame is analysed in.
+const GRID: usize = 8;
+/// WCAG 2: a general flash is a pair of opposing changes in relative
+/// luminance of 0.1 or more where the darker image is below 0.8.
+const GENERAL_DELTA: f32 = 0.1;
+const GENERAL_MAX_DARK: f32 = 0.8;
+/// WCAG 2: a red flash is a pair of opposing changes of 20 or more in
+/// `(R - G - B) * 320` of saturated red, meaning `R / (R + G + B) >= 0.8`.
+const RED_DELTA: f32 = 20.0;
+const RED_SATURATION: f32 = 0.8;
+/// More than three flashes, or six transitions, in any one second fail.
+const MAX_TRANSITIONS: usize = 6;
+const WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashKind {
+    General,
+    Red,
+}
+
+/// Counts opposing transitions of one measurement over time.
+#[derive(Clone, Debug, Default)]
+struct Transitions {
+    extreme: Option<f32>,
+    rising: Option<bool>,
+    times: VecDeque<Instant>,
+}
+
+impl Transitions {
+    fn update(&mut self, value: f32, now: Instant, delta: f32, max_dark: f32) -> usize {
+        let extreme = *self.extreme.get_or_insert(value);
+        let change = value - extreme;
+        let continues = match self.rising {
+            Some(true) => change > 0.0,
+            Some(false) => change < 0.0,
+            None => false,
+        };
+        if continues {
+            self.extreme = Some(value);
+        } else if change.abs() >= delta && extreme.min(value) < max_dark {
+            self.rising = Some(change > 0.0);
+            self.extreme = Some(value);
+            self.times.push_back(now);
+        }
+        while let Some(&t) = self.times.front() {
+            if now.saturating_duration_since(t) > WINDOW {
+                self.times.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.times.len()
+    }
+}
+
+/// Finds content that flashes more than three times a second, following the
+/// WCAG 2 general flash and red flash thresholds. The frame is split into a
+/// grid and a flash is reported once at least `min_area` of it flashes.
+pub struct FlashDetector {
+    pub min_area: f32,
+    general: Vec<Transitions>,
+    red: Vec<Transitions>,
+}
+
+impl Default for FlashDetector {
+    fn default() -> Self {
+        FlashDetector {
+            min_area: 0.25,
+            general: vec![Transitions::default(); GRID * GRID],
+            red: vec![Transitions::default(); GRID * GRID],
+        }
+    }
+}
+
+impl FlashDetector {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Feeds one frame taken at `now`.
+    pub fn update(&mut self, frame: &Frame, now: Instant) -> Option<FlashKind> {
+        let mut general = 0;
+        let mut red = 0;
+        for (i, [luminance, redness]) in cell_means(frame).into_iter().enumerate() {
+            if self.general[i].update(luminance, now, GENERAL_DELTA, GENERAL_MAX_DARK)
+                > MAX_TRANSITIONS
+            {
+                general += 1;
+            }
+            if self.red[i].update(redness, now, RED_DELTA, f32::INFINITY) > MAX_TRANSITIONS {
+                red += 1;
+            }
+        }
+        let needed = self.min_area * (GRID * GRID) as f32;
+        if red as f32 >= needed {
+            Some(FlashKind::Red)
+        } else if general as f32 >= needed {
+            Some(FlashKind::General)
+        } else {
+            None
+        }
+    }
+}
+
+/// Mean relative luminance and red flash measure of each grid cell.
+fn cell_means(frame: &Frame) -> Vec<[f32; 2]> {
+    let mut sums = vec![[0.0f32; 4]; GRID * GRID];
+    let mut counts = vec![0u32; GRID * GRID];
+    for y in 0..frame.height {
+        let row = y * GRID / frame.height.max(1);
+        for x in 0..frame.width {
+            let col = x * GRID / frame.width.max(1);
+            let p = &frame.data[(y * frame.width + x) * 4..][..4];
+            let cell = row * GRID + col;
+            sums[cell][0] += pixel_luminance([p[0], p[1], p[2]]);
+            sums[cell][1] += color::decode(p[2]);
+            sums[cell][2] += color::decode(p[1]);
+            sums[cell][3] += color::decode(p[0]);
+            counts[cell] += 1;
+        }
+    }
+    sums.iter()
+        .zip(counts.iter())
+        .map(|(sum, &n)| {
+            let n = n.max(1) as f32;
+            let [y, r, g, b] = sum.map(|s| s / n);
+            let redness = if r + g + b > 0.0 && r / (r + g + b) >= RED_SATURATION {
+                (r - g - b).max(0.0) * 320.0
+            } else {
+                0.0
+            };
+            [y, redness]
+        })
+        .collect()
+}
+
+/// Damps flashing content by blending each frame with the previous output
+/// while flashing is detected, and for `settle` afterwards.
+pub struct FlashGuard {
+    pub detector: FlashDetector,
+    /// Fraction of the change between frames let through while damping.
+    pub rate: f32,
+    pub settle: Duration,
+    previous: Frame,
+    damping_until: Option<Instant>,
+}
+
+impl FlashGuard {
+    pub fn new() -> Self {
+        FlashGuard {
+            detector: FlashDetector::new(),
+            rate: 0.1,
+            settle: Duration::from_secs(2),
+            previous: Frame::default(),
+            damping_until: None,
+        }
+    }
+
+    pub fn is_damping(&self) -> bool {
+        self.damping_until.is_some()
+    }
+}
+
+impl Default for FlashGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Filter for FlashGuard {
+    fn apply(&mut self, frame: &mut Frame, info: &FrameInfo) {
+        let now = info.time;
+        if let Some(kind) = self.detector.update(frame, now) {
+            if self.damping_until.is_none() {
+                println!("{:?} flashing detected, damping output", kind);
+            }
+            self.damping_until = Some(now + self.settle);
+        } else if self.damping_until.is_some_and(|until| now >= until) {
+            println!("flashing settled");
+            self.damping_until = None;
+        }
+
+        let same_size = self.previous.width == frame.width && self.previous.height == frame.height;
+        if self.is_damping() && same_size {
+            frame.mix(&self.previous, self.rate);
+        }
+        self.previous.clone_from(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_TIME: Duration = Duration::from_micros(16_667);
+
+    fn solid(bgr: [u8; 3]) -> Frame {
+        let mut frame = Frame::new(16, 16);
+        for pixel in frame.pixels_mut() {
+            pixel.copy_from_slice(&[bgr[0], bgr[1], bgr[2], 255]);
+        }
+        frame
+    }
+
+    /// Feeds two seconds of 60fps video alternating between `a` and `b`
+    /// `hz` times a second, and returns the last detection.
+    fn alternate(hz: u32, a: [u8; 3], b: [u8; 3]) -> Option<FlashKind> {
+        let (a, b) = (solid(a), solid(b));
+        let start = Instant::now();
+        let mut detector = FlashDetector::new();
+        let mut kind = None;
+        for i in 0..120u32 {
+            let t = FRAME_TIME * i;
+            let phase = t.as_millis() as u32 * hz * 2 / 1000;
+            let frame = if phase.is_multiple_of(2) { &a } else { &b };
+            kind = detector.update(frame, start + t);
+        }
+        kind
+    }
+
+    #[test]
+    fn fast_alternation_is_a_general_flash() {
+        assert_eq!(
+            alternate(10, [0, 0, 0], [255, 255, 255]),
+            Some(FlashKind::General)
+        );
+    }
+
+    #[test]
+    fn slow_alternation_is_not_a_flash() {
+        assert_eq!(alternate(2, [0, 0, 0], [255, 255, 255]), None);
+        // small changes do not count however fast they are
+        assert_eq!(alternate(10, [100, 100, 100], [110, 110, 110]), None);
+    }
+
+    #[test]
+    fn saturated_red_is_a_red_flash() {
+        assert_eq!(alternate(10, [0, 0, 0], [0, 0, 255]), Some(FlashKind::Red));
+    }
+
+    #[test]
+    fn damping_ends_after_settle() {
+        let (black, white) = (solid([0, 0, 0]), solid([255, 255, 255]));
+        let start = Instant::now();
+        let mut guard = FlashGuard::new();
+        let mut t = Duration::ZERO;
+        let run = |guard: &mut FlashGuard, input: &Frame, t: Duration| {
+            let mut frame = input.clone();
+            let info = FrameInfo {
+                time: start + t,
+                ..FrameInfo::new(0, 0, 0)
+            };
+            guard.apply(&mut frame, &info);
+            frame
+        };
+
+        for i in 0..60u32 {
+            t = FRAME_TIME * i;
+            let input = if (i / 3).is_multiple_of(2) {
+                &black
+            } else {
+                &white
+            };
+            let output = run(&mut guard, input, t);
+            if guard.is_damping() {
+                // only a fraction of each change gets through
+                assert_ne!(output.data, input.data);
+            }
+        }
+        assert!(guard.is_damping());
+
+        // steady content keeps being damped for `settle`, then passes through
+        let flashing_stopped = t;
+        while t < flashing_stopped + guard.settle + Duration::from_secs(1) {
+            t += FRAME_TIME;
+            run(&mut guard, &black, t);
+            if !guard.is_damping() {
+                break;
+            }
+        }
+        assert!(!guard.is_damping());
+        assert!(t - flashing_stopped >= guard.settle);
+        assert_eq!(run(&mut guard, &white, t + FRAME_TIME).data, white.data);
+    }
+}
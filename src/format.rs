@@ -0,0 +1,195 @@
+use crate::color;
+
+/// Luminance of scRGB 1.0, in nits.
+pub const SCRGB_WHITE_NITS: f32 = 80.0;
+/// Largest finite half precision float.
+const F16_MAX: f32 = 65504.0;
+
+/// Layout of the pixels handed over by screen capture. The filters always
+/// work on BGRA8, so other formats are converted with [`PixelFormat::to_bgra8`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 8 bit sRGB, blue first. What SDR displays give.
+    Bgra8,
+    /// 16 bit float linear scRGB, red first. What HDR displays give, with
+    /// values above 1 for anything brighter than SDR white.
+    Rgba16Float,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Bgra8 => 4,
+            PixelFormat::Rgba16Float => 8,
+        }
+    }
+
+    /// Converts one row of `src` to BGRA8, appending it to `dst`.
+    pub fn to_bgra8(&self, src: &[u8], tone_map: &ToneMap, dst: &mut Vec<u8>) {
+        match self {
+            PixelFormat::Bgra8 => dst.extend_from_slice(src),
+            PixelFormat::Rgba16Float => {
+                for p in src.chunks_exact(8) {
+                    let channel = |i: usize| f16_to_f32(u16::from_le_bytes([p[i], p[i + 1]]));
+                    let [r, g, b] = tone_map.apply([channel(0), channel(2), channel(4)]);
+                    dst.extend_from_slice(&[
+                        color::encode(b),
+                        color::encode(g),
+                        color::encode(r),
+                        255,
+                    ]);
+                }
+            }
+        }
+    }
+}
+
+/// Decodes an IEEE 754 half precision float.
+pub fn f16_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((h >> 10) & 0x1f) as i32;
+    let mantissa = (h & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * (-24f32).exp2(),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * ((exponent - 15) as f32).exp2(),
+    }
+}
+
+/// Brings linear scRGB into the SDR range the filters expect.
+///
+/// Luminance is measured relative to `sdr_white`, so SDR windows on an HDR
+/// desktop look much as they would on an SDR one. Up to `knee` of it passes
+/// through unchanged, and everything above rolls off smoothly until `peak`
+/// reaches white, which leaves SDR white itself a little below full white.
+/// Colours outside sRGB are clipped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMap {
+    /// SDR white level of the display, in nits.
+    pub sdr_white: f32,
+    /// Brightest luminance to keep detail in, in nits.
+    pub peak: f32,
+    pub knee: f32,
+}
+
+impl Default for ToneMap {
+    fn default() -> Self {
+        ToneMap::new(200.0, 1000.0)
+    }
+}
+
+impl ToneMap {
+    pub fn new(sdr_white: f32, peak: f32) -> Self {
+        ToneMap {
+            sdr_white,
+            peak: peak.max(sdr_white),
+            knee: 0.9,
+        }
+    }
+
+    /// Maps linear scRGB to linear sRGB from 0 to 1.
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let scale = SCRGB_WHITE_NITS / self.sdr_white;
+        let rgb = rgb.map(|c| {
+            if c.is_nan() {
+                0.0
+            } else {
+                c.clamp(0.0, F16_MAX) * scale
+            }
+        });
+        let luminance = color::luminance(rgb);
+        if luminance <= self.knee {
+            return rgb.map(|c| c.min(1.0));
+        }
+        // extended Reinhard on the part above the knee, reaching 1 at the
+        // peak with the same slope as below the knee
+        let range = 1.0 - self.knee;
+        let t = (luminance - self.knee) / range;
+        let limit = ((self.peak / self.sdr_white - self.knee) / range).max(1e-3);
+        let mapped = t * (1.0 + t / (limit * limit)) / (1.0 + t);
+        let target = (self.knee + range * mapped.min(1.0)).min(1.0);
+        rgb.map(|c| (c * target / luminance).min(1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f16_normal() {
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x3555), 0.333_251_95);
+        assert_eq!(f16_to_f32(0x0400), (-14f32).exp2());
+        assert_eq!(f16_to_f32(0x7bff), F16_MAX);
+    }
+
+    #[test]
+    fn f16_subnormal() {
+        assert_eq!(f16_to_f32(0x0000), 0.0);
+        assert!(f16_to_f32(0x8000) == 0.0 && f16_to_f32(0x8000).is_sign_negative());
+        assert_eq!(f16_to_f32(0x0001), (-24f32).exp2());
+        assert_eq!(f16_to_f32(0x03ff), 1023.0 * (-24f32).exp2());
+        assert_eq!(f16_to_f32(0x8200), -(-15f32).exp2());
+    }
+
+    #[test]
+    fn f16_special() {
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert_eq!(f16_to_f32(0xfc00), f32::NEG_INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+        assert!(f16_to_f32(0x7c01).is_nan());
+        assert!(f16_to_f32(0xfe00).is_nan());
+    }
+
+    #[test]
+    fn tone_map_is_identity_below_knee() {
+        let tone_map = ToneMap::new(200.0, 1000.0);
+        // scRGB is relative to 80 nits, so 200 nit SDR white is 2.5
+        for rgb in [[0.0; 3], [1.0, 0.5, 0.25], [2.0, 2.0, 2.0], [0.2, 2.5, 0.1]] {
+            let expected = rgb.map(|c| c * (SCRGB_WHITE_NITS / 200.0));
+            assert!(color::luminance(expected) <= tone_map.knee);
+            assert_eq!(tone_map.apply(rgb), expected);
+        }
+    }
+
+    #[test]
+    fn tone_map_peak_reaches_white() {
+        for (sdr_white, peak) in [(200.0, 1000.0), (80.0, 400.0), (300.0, 4000.0)] {
+            let tone_map = ToneMap::new(sdr_white, peak);
+            let white = tone_map.apply([peak / SCRGB_WHITE_NITS; 3]);
+            assert!(white.iter().all(|&c| (c - 1.0).abs() < 1e-4), "{:?}", white);
+            // above the knee brighter input stays brighter until the peak
+            let mut last = 0.0;
+            for nits in [sdr_white, (sdr_white + peak) / 2.0, peak * 0.9] {
+                let [y, _, _] = tone_map.apply([nits / SCRGB_WHITE_NITS; 3]);
+                assert!(y > last && y < 1.0);
+                last = y;
+            }
+            assert_eq!(tone_map.apply([peak * 4.0 / SCRGB_WHITE_NITS; 3]), [1.0; 3]);
+        }
+    }
+
+    #[test]
+    fn tone_map_nan_and_negative_are_black() {
+        let tone_map = ToneMap::default();
+        assert_eq!(tone_map.apply([f32::NAN; 3]), [0.0; 3]);
+        assert_eq!(
+            tone_map.apply([f32::NAN, -1.0, f32::NEG_INFINITY]),
+            [0.0; 3]
+        );
+        assert_eq!(tone_map.apply([f32::INFINITY; 3]), [1.0; 3]);
+    }
+
+    #[test]
+    fn rgba16_float_to_bgra8() {
+        // red at SDR white, then a NaN pixel, as little endian halves
+        let halves: [u16; 8] = [0x4100, 0, 0, 0x3c00, 0x7e00, 0x7e00, 0x7e00, 0x3c00];
+        let src: Vec<u8> = halves.iter().flat_map(|h| h.to_le_bytes()).collect();
+        let mut dst = vec![];
+        PixelFormat::Rgba16Float.to_bgra8(&src, &ToneMap::default(), &mut dst);
+        assert_eq!(dst, [0, 0, 255, 255, 0, 0, 0, 255]);
+    }
+}
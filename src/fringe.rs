@@ -0,0 +1,201 @@
+use crate::color::smoothstep;
+use crate::filter::{Filter, Frame, FrameInfo};
+
+/// Pixels either side of a pixel looked at when checking whether its colour
+/// is a subpixel fringe.
+const RADIUS: usize = 3;
+/// Local range in luma above which a pixel is treated as a text edge.
+const EDGE_LOW: f32 = 0.1;
+const EDGE_HIGH: f32 = 0.3;
+/// How much the colours around a pixel must cancel out for it to be treated
+/// as a fringe, from 0 (all one hue) to 1 (perfectly opposite hues).
+const CANCEL_LOW: f32 = 0.2;
+const CANCEL_HIGH: f32 = 0.5;
+
+/// Removes the orange and blue fringes that subpixel antialiased (ClearType)
+/// text gets once it is inverted. Put it after the inversion in the pipeline.
+///
+/// Subpixel rendering shifts each channel of grey text by a third of a pixel,
+/// tinting the two edges of a glyph stem in opposite directions, so along a
+/// row the colours cancel out over a few pixels. Coloured text and images
+/// do not, which is what tells them apart.
+/// Fringe pixels on a strong luma edge are desaturated towards their own
+/// luma, with `strength` scaling how much.
+pub struct FringeSuppress {
+    pub strength: f32,
+    luma: Vec<f32>,
+    chroma: Vec<[f32; 3]>,
+}
+
+impl FringeSuppress {
+    pub fn new(strength: f32) -> Self {
+        FringeSuppress {
+            strength: strength.clamp(0.0, 1.0),
+            luma: Vec::new(),
+            chroma: Vec::new(),
+        }
+    }
+}
+
+impl Default for FringeSuppress {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+fn length(c: [f32; 3]) -> f32 {
+    (c[0] * c[0] + c[1] * c[1] + c[2] * c[2]).sqrt()
+}
+
+impl Filter for FringeSuppress {
+    fn apply(&mut self, frame: &mut Frame, _info: &FrameInfo) {
+        let width = frame.width;
+        if width == 0 || self.strength <= 0.0 {
+            return;
+        }
+        for row in frame.data.chunks_exact_mut(width * 4) {
+            self.luma.clear();
+            self.chroma.clear();
+            for p in row.chunks_exact(4) {
+                let rgb = [p[2], p[1], p[0]].map(|c| c as f32 / 255.0);
+                let y = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+                self.luma.push(y);
+                self.chroma.push(rgb.map(|c| c - y));
+            }
+            // grey rows, the bulk of most text, need no work
+            if self.chroma.iter().all(|&c| length(c) < 1e-3) {
+                continue;
+            }
+            for x in 0..width {
+                let own = length(self.chroma[x]);
+                if own < 1e-3 {
+                    continue;
+                }
+                let window = x.saturating_sub(RADIUS)..(x + RADIUS + 1).min(width);
+                let (mut min, mut max) = (f32::INFINITY, f32::NEG_INFINITY);
+                let mut sum = [0.0; 3];
+                let mut total = 0.0;
+                for i in window {
+                    min = min.min(self.luma[i]);
+                    max = max.max(self.luma[i]);
+                    let c = self.chroma[i];
+                    for k in 0..3 {
+                        sum[k] += c[k];
+                    }
+                    total += length(c);
+                }
+                let edge = smoothstep(EDGE_LOW, EDGE_HIGH, max - min);
+                let cancel = smoothstep(CANCEL_LOW, CANCEL_HIGH, 1.0 - length(sum) / total);
+                let keep = 1.0 - self.strength * edge * cancel;
+                if keep >= 1.0 {
+                    continue;
+                }
+                let y = self.luma[x];
+                let c = self.chroma[x];
+                let p = &mut row[x * 4..x * 4 + 3];
+                for (k, out) in [2, 1, 0].into_iter().enumerate() {
+                    p[out] = ((y + c[k] * keep) * 255.0).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Invert;
+
+    /// Left edge and width, in pixels, of the stems of a few glyphs. The
+    /// edges fall on different subpixels.
+    const STEMS: [(f32, f32); 3] = [(2.0, 1.0), (6.33, 1.33), (11.67, 2.0)];
+    const WIDTH: usize = 16;
+
+    /// Renders `STEMS` in `text` over white the way ClearType does: coverage
+    /// is taken per subpixel, smoothed with a five tap filter so the colour
+    /// fringes stay faint, then blended per channel. Returns one BGRA row.
+    fn render(text: [u8; 3]) -> Frame {
+        let subpixels = WIDTH * 3;
+        let coverage: Vec<f32> = (0..subpixels)
+            .map(|s| {
+                let (left, right) = (s as f32 / 3.0, (s + 1) as f32 / 3.0);
+                STEMS
+                    .iter()
+                    .map(|&(x, w)| ((right.min(x + w) - left.max(x)) * 3.0).clamp(0.0, 1.0))
+                    .sum()
+            })
+            .collect();
+        let taps = [1.0, 2.0, 3.0, 2.0, 1.0];
+        let filtered = |s: usize| -> f32 {
+            let mut sum = 0.0;
+            for (k, tap) in taps.iter().enumerate() {
+                if let Some(c) = (s + k).checked_sub(2).and_then(|i| coverage.get(i)) {
+                    sum += tap * c;
+                }
+            }
+            sum / 9.0
+        };
+        let mut frame = Frame::new(WIDTH, 1);
+        for (x, pixel) in frame.pixels_mut().enumerate() {
+            for (c, out) in [2, 1, 0].into_iter().enumerate() {
+                let a = filtered(x * 3 + c);
+                pixel[out] = (255.0 * (1.0 - a) + text[c] as f32 * a).round() as u8;
+            }
+            pixel[3] = 255;
+        }
+        frame
+    }
+
+    fn invert_and_suppress(mut frame: Frame) -> Frame {
+        let info = FrameInfo::new(0, 0, 0);
+        Invert.apply(&mut frame, &info);
+        FringeSuppress::default().apply(&mut frame, &info);
+        frame
+    }
+
+    fn chroma(p: &[u8]) -> u8 {
+        p[..3].iter().max().unwrap() - p[..3].iter().min().unwrap()
+    }
+
+    #[test]
+    fn grey_text_golden() {
+        let rendered = render([0, 0, 0]);
+        assert!(rendered.data.chunks_exact(4).any(|p| chroma(p) > 100));
+
+        let frame = invert_and_suppress(rendered);
+        #[rustfmt::skip]
+        let expected = [
+            0, 0, 0, 255, 26, 26, 26, 255, 190, 190, 190, 255, 38, 38, 38, 255,
+            0, 0, 0, 255, 2, 2, 2, 255, 157, 157, 157, 255, 174, 174, 174, 255,
+            6, 6, 6, 255, 0, 0, 0, 255, 0, 0, 0, 255, 78, 78, 78, 255,
+            249, 249, 249, 255, 177, 177, 177, 255, 6, 6, 6, 255, 0, 0, 0, 255,
+        ];
+        assert_eq!(frame.data, expected);
+        assert!(frame.data.chunks_exact(4).all(|p| chroma(p) == 0));
+    }
+
+    #[test]
+    fn red_text_golden() {
+        let rendered = render([200, 0, 0]);
+        let mut inverted = rendered.clone();
+        Invert.apply(&mut inverted, &FrameInfo::new(0, 0, 0));
+
+        let frame = invert_and_suppress(rendered);
+        #[rustfmt::skip]
+        let expected = [
+            0, 0, 0, 255, 83, 28, 1, 255, 169, 192, 58, 255, 0, 28, 18, 255,
+            0, 0, 0, 255, 29, 0, 0, 255, 209, 165, 44, 255, 86, 167, 52, 255,
+            0, 0, 6, 255, 0, 0, 0, 255, 0, 0, 0, 255, 169, 84, 6, 255,
+            255, 255, 49, 255, 86, 171, 49, 255, 0, 0, 6, 255, 0, 0, 0, 255,
+        ];
+        assert_eq!(frame.data, expected);
+        // the colour of the text survives, fringes and all
+        let total = |f: &Frame| {
+            f.data
+                .chunks_exact(4)
+                .map(|p| chroma(p) as u32)
+                .sum::<u32>()
+        };
+        assert!(total(&frame) * 10 >= total(&inverted) * 9);
+    }
+}
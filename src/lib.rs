@@ -1,10 +1,50 @@
+pub mod analysis;
 mod cache;
+mod color;
+pub mod cvd;
+pub mod decision;
+pub mod detect;
+pub mod dither;
+pub mod filter;
+pub mod flash;
+pub mod format;
+pub mod fringe;
+pub mod local;
+pub mod lut;
+pub mod mask;
+pub mod palette;
+mod platform;
+pub mod protect;
+#[cfg(windows)]
 mod record;
+pub mod sharpen;
+pub mod source;
+pub mod temperature;
+pub mod threshold;
+#[cfg(windows)]
 mod win;
+#[cfg(target_os = "linux")]
+mod x11_record;
+#[cfg(target_os = "linux")]
+mod x11_win;
 
+use crate::analysis::{LuminanceStats, Policy};
+use crate::decision::{Controller, SlewLimiter, TargetDimmer};
+use crate::detect::PhotoDetector;
+use crate::dither::Dither;
+use crate::filter::{Brightness, Filter, FrameInfo, Pipeline};
+use crate::flash::FlashGuard;
+use crate::format::{PixelFormat, ToneMap};
+use crate::local::LocalAdaptive;
+use crate::mask::Mask;
+use crate::platform::{Native, Platform, TrackEvent};
+use crate::protect::{ProtectMode, Protected};
+#[cfg(windows)]
 use crate::record::ScreenRecorder;
+use crate::source::FrameSource;
+#[cfg(target_os = "linux")]
+use crate::x11_record::X11Recorder;
 use std::{
-    cmp::{max, min},
     collections::hash_map::DefaultHasher,
     default::Default,
     hash::Hasher,
@@ -16,10 +56,12 @@ use std::{
     time::Duration,
 };
 
+#[cfg(target_os = "linux")]
+use winit::platform::x11::EventLoopBuilderExtX11;
 use winit::{
     event::{Event, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::{WindowBuilder, WindowLevel},
+    event_loop::{ControlFlow, EventLoopBuilder},
+    window::WindowBuilder,
 };
 
 use pixels::{Error, Pixels, SurfaceTexture};
@@ -37,19 +79,88 @@ pub fn main() -> Result<(), Error> {
         .and_then(|s| s.parse::<isize>().ok());
     let track_foreground_win = std::env::var("SHADES_TRACK_FOREGROUND_WIN").as_deref() == Ok("1");
     let maximized = std::env::var("SHADES_MAXIMIZED").as_deref() == Ok("1");
+    let default_filters = match std::env::var("SHADES_INVERT_MODE").as_deref() {
+        Ok("lightness") => "lightness-invert",
+        Ok("threshold") => "threshold",
+        _ => "invert",
+    };
+    let filters = std::env::var("SHADES_FILTERS").ok();
+    let dither = std::env::var("SHADES_DITHER")
+        .ok()
+        .map(|s| Dither::parse(&s).expect("invalid SHADES_DITHER"));
+    let mut pipeline = Pipeline::parse(filters.as_deref().unwrap_or(default_filters))
+        .expect("invalid SHADES_FILTERS")
+        .linear(std::env::var("SHADES_LINEAR_LIGHT").as_deref() == Ok("1"))
+        .dither(dither);
+    let policy = std::env::var("SHADES_AUTO_DARK")
+        .map(|s| Policy::parse(&s).expect("invalid SHADES_AUTO_DARK"))
+        .unwrap_or_default();
+    let mut controller = Controller::new(
+        policy.threshold,
+        env_f32("SHADES_AUTO_DARK_OFF").unwrap_or(policy.threshold * 0.8),
+        Duration::from_millis(env_f32("SHADES_AUTO_DARK_DWELL_MS").unwrap_or(500.0) as u64),
+        env_f32("SHADES_FADE_FRAMES").unwrap_or(0.0) as u32,
+    );
+    let mut local = env_f32("SHADES_LOCAL_TILES")
+        .map(|size| LocalAdaptive::new(size as usize, policy, controller.clone()));
+    let mut detector = match std::env::var("SHADES_SMART_INVERT").as_deref() {
+        Ok("1") => Some(PhotoDetector::new(32)),
+        _ => None,
+    };
+    let mut dimmer = env_f32("SHADES_TARGET_LUMINANCE").map(TargetDimmer::new);
+    if dimmer.is_some() {
+        // these only feed the on/off decision the dimmer replaces
+        for name in [
+            "SHADES_INVERT_MODE",
+            "SHADES_LOCAL_TILES",
+            "SHADES_SMART_INVERT",
+        ] {
+            assert!(
+                std::env::var_os(name).is_none(),
+                "SHADES_TARGET_LUMINANCE cannot be combined with {}",
+                name
+            );
+        }
+    }
+    let mut limiter = std::env::var("SHADES_MAX_RISE").ok().map(|s| {
+        let max_rise = s.parse::<f32>().ok().filter(|&r| r > 0.0 && r.is_finite());
+        SlewLimiter::new(max_rise.expect("invalid SHADES_MAX_RISE, expected a positive number"))
+    });
+    let protected = std::env::var("SHADES_PROTECT").ok().map(|spec| {
+        let mode = std::env::var("SHADES_PROTECT_MODE")
+            .map(|s| ProtectMode::parse(&s).expect("invalid SHADES_PROTECT_MODE"))
+            .unwrap_or(ProtectMode::Keep);
+        Protected::parse(&spec, mode).expect("invalid SHADES_PROTECT")
+    });
+    let mut flash_guard = match std::env::var("SHADES_FLASH_GUARD").as_deref() {
+        Ok("1") => Some(FlashGuard::new()),
+        _ => None,
+    };
+
+    let capture_format = match std::env::var("SHADES_HDR").as_deref() {
+        Ok("1") => PixelFormat::Rgba16Float,
+        _ => PixelFormat::Bgra8,
+    };
+    let tone_map = ToneMap::new(
+        env_f32("SHADES_SDR_WHITE").unwrap_or(200.0),
+        env_f32("SHADES_HDR_PEAK").unwrap_or(1000.0),
+    );
 
     let last_pos = cache::get_last_pos();
 
-    let event_loop = EventLoop::new();
+    // Linux only has an X11 backend. XWayland can only capture X clients,
+    // so on a Wayland desktop native Wayland windows read as black
+    if cfg!(target_os = "linux") && std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        println!("Wayland is not supported, native Wayland windows will not be captured");
+    }
+    #[cfg(target_os = "linux")]
+    let event_loop = EventLoopBuilder::new().with_x11().build();
+    #[cfg(not(target_os = "linux"))]
+    let event_loop = EventLoopBuilder::new().build();
     let mut window_builder = WindowBuilder::new()
         .with_title("Shades")
         .with_visible(false)
         .with_decorations(show_decoration)
-        .with_window_level(if always_on_top {
-            WindowLevel::AlwaysOnTop
-        } else {
-            WindowLevel::Normal
-        })
         .with_maximized(maximized);
     if let Some((pos, size)) = last_pos {
         println!("restoring pos: {:?}", &pos);
@@ -62,25 +173,35 @@ pub fn main() -> Result<(), Error> {
 
     let id = window.id();
 
-    win::hide_from_capture(&window).expect("could not hide window from capture");
+    Native::hide_from_capture(&window).expect("could not hide window from capture");
+    if always_on_top {
+        Native::set_always_on_top(&window);
+    }
 
-    use winit::platform::windows::WindowExtWindows;
-    println!("hwnd={:?}, pid={}", window.hwnd(), std::process::id());
+    println!(
+        "hwnd={:?}, pid={}",
+        Native::window_handle(&window),
+        std::process::id()
+    );
 
     if let Some(parent) = parent_win {
-        win::set_parent(&window, parent);
+        Native::set_parent(&window, parent);
     }
 
     let (pix_sender, pix_receiver) = std::sync::mpsc::sync_channel(1);
+    let handle = Native::window_handle(&window);
     let window = Arc::new(window);
     let winref = window.clone();
     std::thread::spawn(move || {
-        let recorder = ScreenRecorder::capture_primary().expect("could not capture primary");
+        let mut source = open_source(capture_format, handle);
 
         let mut last_hash = 0;
         let mut hasher: DefaultHasher = Default::default();
         loop {
-            let pix = recorder.next().expect("could  not take screenshot");
+            let pix = source
+                .next()
+                .expect("could  not take screenshot")
+                .to_bgra8(&tone_map);
             hasher.write(&pix.data.lock().unwrap());
             let hash = hasher.finish();
             if hash != last_hash {
@@ -101,17 +222,17 @@ pub fn main() -> Result<(), Error> {
     });
 
     if track_win.is_none() && track_foreground_win {
-        track_win = Some(win::get_foreground_hwnd());
+        track_win = Some(Native::get_foreground_window());
     }
 
     let request_close = Arc::new(AtomicBool::new(false));
     if let Some(hwnd) = track_win {
         let window = Arc::clone(&window);
         let request_close = Arc::clone(&request_close);
-        win::track(hwnd, move |event| match event {
+        Native::track(hwnd, move |event| match event {
             Some(event) => match event {
-                win::TrackEvent::Size(size) => window.set_inner_size(size),
-                win::TrackEvent::Position(pos) => window.set_outer_position(pos),
+                TrackEvent::Size(size) => window.set_inner_size(size),
+                TrackEvent::Position(pos) => window.set_outer_position(pos),
             },
             None => request_close.store(true, Ordering::Relaxed),
         });
@@ -131,9 +252,12 @@ pub fn main() -> Result<(), Error> {
 
     window.set_visible(true);
     if overlay {
-        win::set_transparent(&window);
+        Native::set_transparent(&window);
+        // X window managers still focus windows that take no clicks
+        #[cfg(target_os = "linux")]
+        Native::set_noactivate(&window);
     }
-    win::set_layered(&window);
+    Native::set_layered(&window);
 
     let mut cnt = 0;
     let mut dir = 1;
@@ -141,6 +265,10 @@ pub fn main() -> Result<(), Error> {
     let mut last_hash = 0;
     let mut hasher: DefaultHasher = Default::default();
     let mut hittest = true;
+    let mut region = filter::Frame::default();
+    let mut original = filter::Frame::default();
+    let mut frame_index = 0;
+    let mut animating = false;
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Wait;
 
@@ -161,59 +289,126 @@ pub fn main() -> Result<(), Error> {
                 }
                 tmp
             };
-            if let Some(pix) = pix_opt {
+            // keep drawing the last screenshot until the output settles
+            if let Some(pix) = pix_opt.or(if animating { Some(&pix) } else { None }) {
                 let target_width = window.inner_size().width as usize;
                 let target_height = window.inner_size().height as usize;
                 if target_width == 0 || target_height == 0 {
                     return;
                 }
-                let src_width = pix.width as i32;
                 let mut offset_x = 0;
                 let mut offset_y = 0;
                 if let Ok(pos) = window.inner_position() {
                     offset_x = pos.x;
                     offset_y = pos.y;
                 }
-                let max_j = (pix.width * pix.height - 1) as i32;
-                {
-                    let data = pix.data.lock().unwrap();
-                    let avg_rgb = data
-                        .chunks_exact(src_width as usize * 4)
-                        .skip(max(0, offset_y) as usize)
-                        .take(target_height)
-                        .map(|row| {
-                            let x_left = max(0, min(src_width, offset_x));
-                            let x_right = max(0, min(src_width, target_width as i32 + offset_x));
-                            row[x_left as usize * 4..x_right as usize * 4]
-                                .iter()
-                                .map(|&d| d as f32)
-                                .sum::<f32>()
-                        })
-                        .sum::<f32>()
-                        / (target_width * target_height * 4) as f32;
-
-                    // TODO: use effect intensity instead
-                    let do_invert = avg_rgb > 150.0;
-
-                    for (i, pixel) in pixels.frame_mut().chunks_exact_mut(4).enumerate() {
-                        let col = (i % target_width) as i32;
-                        let row = (i / target_width) as i32;
-                        let j = max(0, min(max_j, (row + offset_y) * src_width + col + offset_x))
-                            as usize;
-
-                        // TODO: use color-preserving invert
-                        // TODO: alternate darkening strategy
-                        if do_invert {
-                            pixel[2] = 255 - data[j * 4];
-                            pixel[1] = 255 - data[j * 4 + 1];
-                            pixel[0] = 255 - data[j * 4 + 2];
-                        } else {
-                            pixel[2] = data[j * 4];
-                            pixel[1] = data[j * 4 + 1];
-                            pixel[0] = data[j * 4 + 2];
+                region.copy_region(
+                    &pix.data.lock().unwrap(),
+                    pix.width as usize,
+                    offset_x - pix.origin.0,
+                    offset_y - pix.origin.1,
+                    target_width,
+                    target_height,
+                );
+                let info = FrameInfo::new(offset_x, offset_y, frame_index);
+                let stats = LuminanceStats::from_frame(&region);
+                if protected.is_some() {
+                    original.clone_from(&region);
+                }
+                let mut dimmed_mean = stats.mean;
+                let mut gain = 1.0;
+                let mut detected = false;
+                let filtered = if let Some(dimmer) = dimmer.as_mut() {
+                    // explicit filters such as a colour temperature still
+                    // apply, and the target is held on their output
+                    if filters.is_some() {
+                        pipeline.apply(&mut region, &info);
+                        dimmed_mean = analysis::mean_luminance(&region);
+                    }
+                    gain = dimmer.update(dimmed_mean);
+                    filters.is_some()
+                } else {
+                    let global_mask;
+                    let weights = match local.as_mut() {
+                        Some(local) => local.classify(&region, info.time),
+                        None => {
+                            let value = policy.statistic.measure(&stats);
+                            let weight = controller.update(value, info.time);
+                            let size = target_width.max(target_height);
+                            global_mask = Mask::new(target_width, target_height, size, weight);
+                            &global_mask
+                        }
+                    };
+                    if weights.uniform() == Some(0.0) {
+                        // nothing is darkened, so there are no photos to spare
+                        false
+                    } else {
+                        let mut masks = vec![weights];
+                        if let Some(detector) = detector.as_mut() {
+                            masks.push(detector.detect(&region));
+                            detected = true;
+                        }
+                        match masks.iter().map(|m| m.uniform()).product::<Option<f32>>() {
+                            Some(w) if w <= 0.0 => false,
+                            Some(w) if w >= 1.0 => {
+                                pipeline.apply(&mut region, &info);
+                                true
+                            }
+                            _ => {
+                                if protected.is_none() {
+                                    original.clone_from(&region);
+                                }
+                                pipeline.apply(&mut region, &info);
+                                region.mix_masked(&original, &masks);
+                                true
+                            }
                         }
-                        hasher.write(pixel);
                     }
+                };
+                if let Some(protected) = protected.as_ref().filter(|_| filtered) {
+                    protected.restore(&mut region, &original);
+                }
+                if let Some(limiter) = limiter.as_mut() {
+                    let luminance = if dimmer.is_some() {
+                        // dimming scales linear light, so the mean scales with it
+                        dimmed_mean * gain
+                    } else if filtered {
+                        analysis::mean_luminance(&region)
+                    } else {
+                        stats.mean
+                    };
+                    gain *= limiter.update(luminance);
+                }
+                // dimming goes last, so it is rounded to 8 bits only once
+                // and dithered there
+                if gain < 1.0 {
+                    let info = FrameInfo {
+                        linear: true,
+                        dither,
+                        ..info
+                    };
+                    Brightness(gain).apply(&mut region, &info);
+                }
+                if let Some(guard) = flash_guard.as_mut() {
+                    guard.apply(&mut region, &info);
+                }
+                animating = match &local {
+                    Some(local) => local.is_fading(),
+                    None => controller.is_changing(),
+                } || dimmer.as_ref().is_some_and(|d| d.is_settling())
+                    || (detected && detector.as_ref().is_some_and(|d| d.is_settling()))
+                    || limiter.as_ref().is_some_and(|l| l.is_limiting())
+                    || flash_guard.as_ref().is_some_and(|g| g.is_damping());
+                if animating {
+                    window.request_redraw();
+                }
+                frame_index += 1;
+
+                for (pixel, src) in pixels.frame_mut().chunks_exact_mut(4).zip(region.pixels()) {
+                    pixel[0] = src[2];
+                    pixel[1] = src[1];
+                    pixel[2] = src[0];
+                    hasher.write(pixel);
                 }
                 let frame = pixels.frame_mut();
                 let flash = (cnt % 16) << 4;
@@ -251,7 +446,7 @@ pub fn main() -> Result<(), Error> {
         let new_hittest = get_hittest(&window);
         if hittest != new_hittest {
             hittest = new_hittest;
-            window.set_cursor_hittest(hittest).unwrap();
+            Native::set_cursor_hittest(&window, hittest);
         }
 
         match event {
@@ -282,8 +477,28 @@ pub fn main() -> Result<(), Error> {
     });
 }
 
+fn env_f32(name: &str) -> Option<f32> {
+    std::env::var(name).ok().and_then(|s| s.parse::<f32>().ok())
+}
+
+#[cfg(windows)]
+fn open_source(format: PixelFormat, _window: isize) -> Box<dyn FrameSource> {
+    // the window is already hidden from capture
+    Box::new(ScreenRecorder::capture_primary(format).expect("could not capture primary"))
+}
+
+#[cfg(target_os = "linux")]
+fn open_source(_format: PixelFormat, window: isize) -> Box<dyn FrameSource> {
+    // X servers only hand out SDR pixels, and cannot hide a window from
+    // capture, so only what is stacked below it is read
+    let recorder = X11Recorder::capture_primary()
+        .and_then(|recorder| recorder.beneath(window as u32))
+        .expect("could not capture primary");
+    Box::new(recorder)
+}
+
 fn get_hittest(window: &winit::window::Window) -> bool {
-    let mouse = win::get_cursor_pos();
+    let mouse = Native::get_cursor_pos();
     let outer_pos = window.outer_position().unwrap();
     let inner_pos = window.inner_position().unwrap();
     let outer_size = window.outer_size();
@@ -0,0 +1,130 @@
+use std::time::Instant;
+
+use crate::analysis::{pixel_luminance, LuminanceStats, Policy};
+use crate::decision::Controller;
+use crate::filter::Frame;
+use crate::mask::Mask;
+
+/// Makes a separate darkening decision for each tile of the frame, so a dark
+/// editor and a bright browser under the same window are both left readable.
+/// Each tile has its own [`Controller`], so tiles get the same hysteresis and
+/// cross-fade as the whole-window decision.
+pub struct LocalAdaptive {
+    pub tile_size: usize,
+    pub policy: Policy,
+    template: Controller,
+    controllers: Vec<Controller>,
+    mask: Mask,
+}
+
+impl LocalAdaptive {
+    pub fn new(tile_size: usize, policy: Policy, template: Controller) -> Self {
+        LocalAdaptive {
+            tile_size: tile_size.max(1),
+            policy,
+            template,
+            controllers: vec![],
+            mask: Mask::default(),
+        }
+    }
+
+    /// Classifies every tile of `frame` and returns the resulting weights.
+    pub fn classify(&mut self, frame: &Frame, now: Instant) -> &Mask {
+        let mask = Mask::new(frame.width, frame.height, self.tile_size, 0.0);
+        if mask.cols != self.mask.cols || mask.rows != self.mask.rows {
+            self.controllers = vec![self.template.clone(); mask.cols * mask.rows];
+        }
+        self.mask = mask;
+
+        for row in 0..self.mask.rows {
+            for col in 0..self.mask.cols {
+                let stats = LuminanceStats::from_luminances(self.tile(frame, col, row));
+                let value = self.policy.statistic.measure(&stats);
+                let weight = self.controllers[row * self.mask.cols + col].update(value, now);
+                self.mask.set(col, row, weight);
+            }
+        }
+        &self.mask
+    }
+
+    /// Some tile is still part way through a cross-fade, or waiting to
+    /// switch.
+    pub fn is_fading(&self) -> bool {
+        self.controllers.iter().any(Controller::is_changing)
+    }
+
+    fn tile<'a>(&self, frame: &'a Frame, col: usize, row: usize) -> impl Iterator<Item = f32> + 'a {
+        let x0 = col * self.tile_size;
+        let x1 = (x0 + self.tile_size).min(frame.width);
+        let y0 = row * self.tile_size;
+        let y1 = (y0 + self.tile_size).min(frame.height);
+        let width = frame.width;
+        (y0..y1).flat_map(move |y| {
+            frame.data[(y * width + x0) * 4..(y * width + x1) * 4]
+                .chunks_exact(4)
+                .map(|p| pixel_luminance([p[0], p[1], p[2]]))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// 64x64 frame that is white in the top-left and bottom-right quadrants
+    /// and black in the other two.
+    fn quadrants() -> Frame {
+        let mut frame = Frame::new(64, 64);
+        for (i, pixel) in frame.pixels_mut().enumerate() {
+            let (x, y) = (i % 64, i / 64);
+            let v = if (x < 32) == (y < 32) { 255 } else { 0 };
+            pixel.copy_from_slice(&[v, v, v, 255]);
+        }
+        frame
+    }
+
+    fn local(dwell: Duration) -> LocalAdaptive {
+        let policy = Policy::default();
+        let controller = Controller::new(policy.threshold, policy.threshold * 0.8, dwell, 0);
+        LocalAdaptive::new(16, policy, controller)
+    }
+
+    #[test]
+    fn classifies_quadrants() {
+        let mut local = local(Duration::ZERO);
+        let mask = local.classify(&quadrants(), Instant::now());
+        assert_eq!((mask.cols, mask.rows), (4, 4));
+        for row in 0..4 {
+            for col in 0..4 {
+                let light = (col < 2) == (row < 2);
+                let expected = if light { 1.0 } else { 0.0 };
+                assert_eq!(mask.get(col, row), expected, "tile {}, {}", col, row);
+            }
+        }
+        // weights are exact at tile centres and blend across the boundary
+        assert_eq!(mask.sample(8, 8), 1.0);
+        assert_eq!(mask.sample(56, 8), 0.0);
+        let across = (0..64).map(|x| mask.sample(x, 8)).collect::<Vec<_>>();
+        assert!(across.windows(2).all(|w| w[1] <= w[0]));
+        assert!((across[31] + across[32] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pending_tiles_keep_fading() {
+        let start = Instant::now();
+        let mut local = local(Duration::from_millis(500));
+        local.classify(&quadrants(), start);
+        assert!(!local.is_fading());
+
+        // the light quadrants go dark before their tiles may switch back
+        let mut dark = quadrants();
+        dark.data.fill(0);
+        let mask = local.classify(&dark, start + Duration::from_millis(100));
+        assert_eq!(mask.get(0, 0), 1.0);
+        assert!(local.is_fading());
+        let mask = local.classify(&dark, start + Duration::from_millis(500));
+        assert_eq!(mask.uniform(), Some(0.0));
+        assert!(!local.is_fading());
+    }
+}
@@ -0,0 +1,276 @@
+use std::{fmt, fs, path::Path};
+
+use crate::filter::{map_pixels, Filter, Frame, FrameInfo};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    /// 1-based line number, or 0 if the problem is with the file as a whole.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    #[default]
+    Trilinear,
+    Tetrahedral,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Table {
+    /// One curve per channel.
+    OneD(Vec<[f32; 3]>),
+    /// A `size` x `size` x `size` cube with red changing fastest.
+    ThreeD { size: usize, values: Vec<[f32; 3]> },
+}
+
+/// A colour lookup table in Adobe/Resolve `.cube` format.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut {
+    pub title: Option<String>,
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    pub table: Table,
+    pub interpolation: Interpolation,
+}
+
+impl Lut {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ParseError> {
+        let text = fs::read_to_string(path.as_ref()).map_err(|e| ParseError {
+            line: 0,
+            message: format!("could not read {}: {}", path.as_ref().display(), e),
+        })?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut title = None;
+        let mut size_1d = None;
+        let mut size_3d = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut values = vec![];
+
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let err = |message: String| ParseError {
+                line: line_no,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let keyword = fields.next().unwrap_or_default();
+            let rest: Vec<&str> = fields.collect();
+            let floats = |n: usize| -> Result<Vec<f32>, ParseError> {
+                if rest.len() != n {
+                    return Err(err(format!(
+                        "{} expects {} values, found {}",
+                        keyword,
+                        n,
+                        rest.len()
+                    )));
+                }
+                rest.iter()
+                    .map(|v| {
+                        v.parse::<f32>()
+                            .map_err(|_| err(format!("'{}' is not a number", v)))
+                    })
+                    .collect()
+            };
+            let size = || -> Result<usize, ParseError> {
+                match rest.as_slice() {
+                    [n] => match n.parse::<usize>() {
+                        Ok(n) if n >= 2 => Ok(n),
+                        _ => Err(err(format!("invalid {} '{}'", keyword, n))),
+                    },
+                    _ => Err(err(format!("{} expects one value", keyword))),
+                }
+            };
+            match keyword {
+                "TITLE" => {
+                    let t = line["TITLE".len()..].trim();
+                    title = Some(t.trim_matches('"').to_string());
+                }
+                "LUT_1D_SIZE" => size_1d = Some(size()?),
+                "LUT_3D_SIZE" => {
+                    let n = size()?;
+                    if n > 256 {
+                        return Err(err(format!("LUT_3D_SIZE {} is larger than 256", n)));
+                    }
+                    size_3d = Some(n);
+                }
+                "DOMAIN_MIN" => domain_min = floats(3)?.try_into().unwrap(),
+                "DOMAIN_MAX" => domain_max = floats(3)?.try_into().unwrap(),
+                "LUT_1D_INPUT_RANGE" | "LUT_3D_INPUT_RANGE" => {
+                    let range = floats(2)?;
+                    domain_min = [range[0]; 3];
+                    domain_max = [range[1]; 3];
+                }
+                k if k.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    return Err(err(format!("unknown keyword '{}'", k)));
+                }
+                _ => {
+                    if line.split_whitespace().count() != 3 {
+                        return Err(err(format!(
+                            "expected 3 values per row, found {}",
+                            line.split_whitespace().count()
+                        )));
+                    }
+                    let mut rgb = [0.0; 3];
+                    for (c, v) in rgb.iter_mut().zip(line.split_whitespace()) {
+                        *c = v
+                            .parse::<f32>()
+                            .map_err(|_| err(format!("'{}' is not a number", v)))?;
+                    }
+                    values.push(rgb);
+                }
+            }
+        }
+
+        let whole = |message: String| ParseError { line: 0, message };
+        if (0..3).any(|c| domain_max[c] <= domain_min[c]) {
+            return Err(whole("DOMAIN_MAX must be greater than DOMAIN_MIN".into()));
+        }
+        let table = match (size_1d, size_3d) {
+            (Some(_), Some(_)) => {
+                return Err(whole("file has both LUT_1D_SIZE and LUT_3D_SIZE".into()))
+            }
+            (None, None) => return Err(whole("missing LUT_1D_SIZE or LUT_3D_SIZE".into())),
+            (Some(n), None) => {
+                if values.len() != n {
+                    return Err(whole(format!(
+                        "LUT_1D_SIZE is {} but found {} rows",
+                        n,
+                        values.len()
+                    )));
+                }
+                Table::OneD(values)
+            }
+            (None, Some(n)) => {
+                if values.len() != n * n * n {
+                    return Err(whole(format!(
+                        "LUT_3D_SIZE is {} so expected {} rows but found {}",
+                        n,
+                        n * n * n,
+                        values.len()
+                    )));
+                }
+                Table::ThreeD { size: n, values }
+            }
+        };
+        Ok(Lut {
+            title,
+            domain_min,
+            domain_max,
+            table,
+            interpolation: Interpolation::default(),
+        })
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    /// Looks up one normalized RGB value.
+    pub fn lookup(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let mut pos = [0.0; 3];
+        for c in 0..3 {
+            pos[c] = ((rgb[c] - self.domain_min[c]) / (self.domain_max[c] - self.domain_min[c]))
+                .clamp(0.0, 1.0);
+        }
+        match &self.table {
+            Table::OneD(values) => {
+                let last = values.len() - 1;
+                let mut out = [0.0; 3];
+                for c in 0..3 {
+                    let p = pos[c] * last as f32;
+                    let i = (p as usize).min(last - 1);
+                    let f = p - i as f32;
+                    out[c] = values[i][c] + (values[i + 1][c] - values[i][c]) * f;
+                }
+                out
+            }
+            Table::ThreeD { size, values } => {
+                let last = size - 1;
+                let mut base = [0; 3];
+                let mut frac = [0.0; 3];
+                for c in 0..3 {
+                    let p = pos[c] * last as f32;
+                    base[c] = (p as usize).min(last - 1);
+                    frac[c] = p - base[c] as f32;
+                }
+                let at = |dr: usize, dg: usize, db: usize| {
+                    values[(base[0] + dr) + (base[1] + dg) * size + (base[2] + db) * size * size]
+                };
+                match self.interpolation {
+                    Interpolation::Trilinear => trilinear(at, frac),
+                    Interpolation::Tetrahedral => tetrahedral(at, frac),
+                }
+            }
+        }
+    }
+}
+
+fn lerp(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [0, 1, 2].map(|c| a[c] + (b[c] - a[c]) * t)
+}
+
+fn trilinear<F: Fn(usize, usize, usize) -> [f32; 3]>(at: F, [fr, fg, fb]: [f32; 3]) -> [f32; 3] {
+    let c00 = lerp(at(0, 0, 0), at(1, 0, 0), fr);
+    let c10 = lerp(at(0, 1, 0), at(1, 1, 0), fr);
+    let c01 = lerp(at(0, 0, 1), at(1, 0, 1), fr);
+    let c11 = lerp(at(0, 1, 1), at(1, 1, 1), fr);
+    lerp(lerp(c00, c10, fg), lerp(c01, c11, fg), fb)
+}
+
+fn tetrahedral<F: Fn(usize, usize, usize) -> [f32; 3]>(at: F, [fr, fg, fb]: [f32; 3]) -> [f32; 3] {
+    let c000 = at(0, 0, 0);
+    let c111 = at(1, 1, 1);
+    // walk from c000 to c111 along the edges of the tetrahedron holding the point
+    let (first, second, weights) = if fr > fg {
+        if fg > fb {
+            (at(1, 0, 0), at(1, 1, 0), [fr, fg, fb])
+        } else if fr > fb {
+            (at(1, 0, 0), at(1, 0, 1), [fr, fb, fg])
+        } else {
+            (at(0, 0, 1), at(1, 0, 1), [fb, fr, fg])
+        }
+    } else if fb > fg {
+        (at(0, 0, 1), at(0, 1, 1), [fb, fg, fr])
+    } else if fb > fr {
+        (at(0, 1, 0), at(0, 1, 1), [fg, fb, fr])
+    } else {
+        (at(0, 1, 0), at(1, 1, 0), [fg, fr, fb])
+    };
+    [0, 1, 2].map(|c| {
+        c000[c]
+            + weights[0] * (first[c] - c000[c])
+            + weights[1] * (second[c] - first[c])
+            + weights[2] * (c111[c] - second[c])
+    })
+}
+
+impl Filter for Lut {
+    fn apply(&mut self, frame: &mut Frame, info: &FrameInfo) {
+        // LUTs are authored for sRGB encoded input and output
+        map_pixels(frame, info, |rgb| {
+            info.from_srgb(self.lookup(info.to_srgb(rgb)))
+        });
+    }
+}
@@ -0,0 +1,103 @@
+/// Filter weights on a coarse grid laid over a frame. Each cell covers
+/// `cell_size` x `cell_size` pixels and its weight applies at the cell centre;
+/// pixels in between are interpolated so cell boundaries are not visible.
+#[derive(Clone, Debug, Default)]
+pub struct Mask {
+    pub cols: usize,
+    pub rows: usize,
+    pub cell_size: usize,
+    pub values: Vec<f32>,
+}
+
+impl Mask {
+    /// A mask of `value` with enough cells to cover `width` x `height`.
+    pub fn new(width: usize, height: usize, cell_size: usize, value: f32) -> Self {
+        let cell_size = cell_size.max(1);
+        let cols = width.div_ceil(cell_size);
+        let rows = height.div_ceil(cell_size);
+        Mask {
+            cols,
+            rows,
+            cell_size,
+            values: vec![value; cols * rows],
+        }
+    }
+
+    pub fn get(&self, col: usize, row: usize) -> f32 {
+        self.values[row * self.cols + col]
+    }
+
+    pub fn set(&mut self, col: usize, row: usize, value: f32) {
+        self.values[row * self.cols + col] = value;
+    }
+
+    /// All cells have this weight, so sampling is not needed.
+    pub fn uniform(&self) -> Option<f32> {
+        let first = *self.values.first()?;
+        self.values.iter().all(|&v| v == first).then_some(first)
+    }
+
+    /// Weight at pixel (`x`, `y`), bilinearly interpolated between cell centres.
+    pub fn sample(&self, x: usize, y: usize) -> f32 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+        let (c0, c1, fx) = Self::axis(x, self.cell_size, self.cols);
+        let (r0, r1, fy) = Self::axis(y, self.cell_size, self.rows);
+        let top = self.get(c0, r0) + (self.get(c1, r0) - self.get(c0, r0)) * fx;
+        let bottom = self.get(c0, r1) + (self.get(c1, r1) - self.get(c0, r1)) * fx;
+        top + (bottom - top) * fy
+    }
+
+    /// Multiplies each cell by the matching cell of `other`, which must have
+    /// the same shape.
+    pub fn multiply(&mut self, other: &Mask) {
+        for (v, o) in self.values.iter_mut().zip(other.values.iter()) {
+            *v *= o;
+        }
+    }
+
+    fn axis(pos: usize, cell_size: usize, cells: usize) -> (usize, usize, f32) {
+        let p = (pos as f32 + 0.5) / cell_size as f32 - 0.5;
+        if p <= 0.0 {
+            return (0, 0, 0.0);
+        }
+        let i = p as usize;
+        if i + 1 >= cells {
+            return (cells - 1, cells - 1, 0.0);
+        }
+        (i, i + 1, p - i as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_interpolates_between_centres() {
+        let mut mask = Mask::new(48, 16, 16, 0.0);
+        mask.set(1, 0, 1.0);
+        // flat up to the first centre, then linear to the next one
+        assert_eq!(mask.sample(0, 0), 0.0);
+        assert_eq!(mask.sample(7, 0), 0.0);
+        assert_eq!(mask.sample(23, 8), mask.sample(24, 8));
+        assert!(mask.sample(24, 8) > 0.95);
+        assert!((mask.sample(15, 8) + mask.sample(16, 8) - 1.0).abs() < 1e-6);
+        assert_eq!(mask.sample(40, 8), 0.0);
+
+        let row = (0..48).map(|x| mask.sample(x, 8)).collect::<Vec<_>>();
+        for pair in row.windows(2) {
+            assert!((pair[1] - pair[0]).abs() <= 1.0 / 16.0 + 1e-6, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn uniform() {
+        let mut mask = Mask::new(40, 40, 16, 0.5);
+        assert_eq!((mask.cols, mask.rows), (3, 3));
+        assert_eq!(mask.uniform(), Some(0.5));
+        mask.set(2, 2, 0.0);
+        assert_eq!(mask.uniform(), None);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::color::luminance;
+use crate::filter::{map_pixels, Filter, Frame, FrameInfo};
+
+const THEMES: &str = include_str!("themes.txt");
+
+/// A background and foreground colour pair, as normalized RGB.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    pub background: [f32; 3],
+    pub foreground: [f32; 3],
+}
+
+impl Theme {
+    /// Looks up one of the themes shipped in `themes.txt`.
+    pub fn named(name: &str) -> Option<Self> {
+        THEMES
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .find(|fields| fields.len() == 3 && fields[0] == name)
+            .and_then(|fields| {
+                Some(Theme {
+                    background: parse_hex(fields[1])?,
+                    foreground: parse_hex(fields[2])?,
+                })
+            })
+    }
+
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        THEMES
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_whitespace().next())
+    }
+}
+
+/// Parses `#rrggbb` into normalized RGB.
+pub fn parse_hex(s: &str) -> Option<[f32; 3]> {
+    let s = s.strip_prefix('#')?;
+    if s.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(s.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?].map(|c| c as f32 / 255.0))
+}
+
+/// Estimates the background colour of a frame as its most common colour, and
+/// the foreground as the most common colour that clearly contrasts with it.
+pub fn estimate_colours(frame: &Frame) -> ([f32; 3], [f32; 3]) {
+    const BITS: usize = 4;
+    const MIN_CONTRAST: f32 = 0.25;
+    let mut counts = vec![0u32; 1 << (3 * BITS)];
+    let mut sums = vec![[0u32; 3]; 1 << (3 * BITS)];
+    // every pixel is not needed for a good estimate
+    for p in frame.data.chunks_exact(4).step_by(3) {
+        let rgb = [p[2], p[1], p[0]];
+        let bin = rgb
+            .iter()
+            .fold(0, |bin, &c| (bin << BITS) | (c as usize >> (8 - BITS)));
+        counts[bin] += 1;
+        for c in 0..3 {
+            sums[bin][c] += rgb[c] as u32;
+        }
+    }
+    let colour = |bin: usize| sums[bin].map(|s| s as f32 / counts[bin].max(1) as f32 / 255.0);
+
+    let Some(bg_bin) = (0..counts.len()).max_by_key(|&i| counts[i]) else {
+        return ([1.0; 3], [0.0; 3]);
+    };
+    let background = colour(bg_bin);
+    let foreground = (0..counts.len())
+        .filter(|&i| {
+            counts[i] > 0 && (luminance(colour(i)) - luminance(background)).abs() > MIN_CONTRAST
+        })
+        .max_by_key(|&i| counts[i])
+        .map(colour)
+        .unwrap_or(if luminance(background) > 0.5 {
+            [0.0; 3]
+        } else {
+            [1.0; 3]
+        });
+    (background, foreground)
+}
+
+/// Maps the frame's own background and foreground colours onto a theme,
+/// interpolating the greys in between. Colourful pixels keep their chroma
+/// on top of the remapped lightness.
+pub struct PaletteRemap {
+    pub theme: Theme,
+}
+
+impl PaletteRemap {
+    pub fn new(theme: Theme) -> Self {
+        PaletteRemap { theme }
+    }
+}
+
+impl Filter for PaletteRemap {
+    fn apply(&mut self, frame: &mut Frame, info: &FrameInfo) {
+        let (background, foreground) = estimate_colours(frame);
+        let (background, foreground) = (info.from_srgb(background), info.from_srgb(foreground));
+        let (bg_luma, fg_luma) = (luminance(background), luminance(foreground));
+        let span = fg_luma - bg_luma;
+        if span.abs() < 1e-3 {
+            return;
+        }
+        let theme = Theme {
+            background: info.from_srgb(self.theme.background),
+            foreground: info.from_srgb(self.theme.foreground),
+        };
+        map_pixels(frame, info, |rgb| {
+            let y = luminance(rgb);
+            let t = ((y - bg_luma) / span).clamp(0.0, 1.0);
+            let mut out = [0.0; 3];
+            for c in 0..3 {
+                let base = theme.background[c] + (theme.foreground[c] - theme.background[c]) * t;
+                out[c] = base + (rgb[c] - y);
+            }
+            out
+        });
+    }
+}
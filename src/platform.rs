@@ -0,0 +1,50 @@
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::window::Window;
+
+pub(crate) enum TrackEvent {
+    Size(PhysicalSize<u32>),
+    Position(PhysicalPosition<i32>),
+}
+
+/// Window behaviour winit does not cover, implemented once per OS so the
+/// render loop calls the same functions everywhere. Windows are identified by
+/// their native handle: an `HWND` on Windows and an XID on X11.
+pub(crate) trait Platform {
+    fn window_handle(window: &Window) -> isize;
+
+    /// Keeps the window out of screen captures, so it never sees its own
+    /// output.
+    fn hide_from_capture(window: &Window) -> Result<(), String>;
+
+    fn set_parent(window: &Window, parent: isize);
+
+    /// Lets mouse clicks pass through to the windows below.
+    fn set_transparent(window: &Window);
+
+    /// Whether the window takes mouse clicks, toggled as the cursor moves
+    /// on and off the window decorations.
+    fn set_cursor_hittest(window: &Window, hittest: bool);
+
+    fn set_layered(window: &Window);
+
+    /// Keeps the window from taking focus or showing in the taskbar.
+    fn set_noactivate(window: &Window);
+
+    fn set_always_on_top(window: &Window);
+
+    /// Mouse position in screen coordinates.
+    fn get_cursor_pos() -> (i32, i32);
+
+    fn get_foreground_window() -> isize;
+
+    /// Reports the position and size of `target` from a background thread,
+    /// and `None` once it is gone.
+    fn track<F>(target: isize, callback: F)
+    where
+        F: Fn(Option<TrackEvent>) + Send + 'static;
+}
+
+#[cfg(windows)]
+pub(crate) type Native = crate::win::Win32;
+#[cfg(target_os = "linux")]
+pub(crate) type Native = crate::x11_win::X11;
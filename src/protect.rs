@@ -0,0 +1,291 @@
+use crate::color;
+use crate::filter::Frame;
+use crate::palette::{estimate_colours, parse_hex};
+
+/// Default distance in normalized sRGB a pixel may be from a protected
+/// colour, or from a blend of it with the background, and still match.
+pub const DEFAULT_TOLERANCE: f32 = 0.06;
+/// Default OKLab chroma a pixel needs to count as part of a hue range.
+pub const DEFAULT_MIN_CHROMA: f32 = 0.08;
+/// Degrees over which a hue range fades in and out.
+const HUE_FEATHER: f32 = 10.0;
+/// OKLab lightness range protected colours are remapped into. Lightness is
+/// inverted and squeezed into it, so light highlights become muted
+/// backgrounds and dark text stays readable on a dark page.
+const REMAP_LIGHTNESS: (f32, f32) = (0.45, 0.8);
+
+/// A colour, or range of colours, the darkening filter must not lose.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protect {
+    /// One sRGB colour, normalized.
+    Colour { rgb: [f32; 3], tolerance: f32 },
+    /// OKLab hues from `from` to `to` degrees, wrapping through 0 if `from`
+    /// is larger.
+    Hue { from: f32, to: f32, min_chroma: f32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtectMode {
+    /// Show protected colours as they are on screen.
+    Keep,
+    /// Keep the hue but move lightness into a range that reads well on a
+    /// dark background.
+    Remap,
+}
+
+impl ProtectMode {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "keep" => Ok(ProtectMode::Keep),
+            "remap" => Ok(ProtectMode::Remap),
+            _ => Err(format!(
+                "unknown protect mode '{}', expected 'keep' or 'remap'",
+                s
+            )),
+        }
+    }
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]).powi(2)).sum::<f32>().sqrt()
+}
+
+impl Protect {
+    /// Parses `#rrggbb`, `#rrggbb~tolerance`, `hue:from-to` or
+    /// `hue:from-to~min_chroma`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (value, extra) = match s.split_once('~') {
+            Some((value, extra)) => {
+                let extra = extra
+                    .parse::<f32>()
+                    .map_err(|_| format!("invalid tolerance '{}' in '{}'", extra, s))?;
+                (value, Some(extra))
+            }
+            None => (s, None),
+        };
+        if let Some(range) = value.strip_prefix("hue:") {
+            let (from, to) = range
+                .split_once('-')
+                .and_then(|(from, to)| Some((from.parse::<f32>().ok()?, to.parse::<f32>().ok()?)))
+                .ok_or_else(|| format!("invalid hue range '{}', expected e.g. 'hue:20-40'", s))?;
+            return Ok(Protect::Hue {
+                from: from.rem_euclid(360.0),
+                to: to.rem_euclid(360.0),
+                min_chroma: extra.unwrap_or(DEFAULT_MIN_CHROMA),
+            });
+        }
+        let rgb = parse_hex(value).ok_or_else(|| format!("invalid colour '{}'", s))?;
+        Ok(Protect::Colour {
+            rgb,
+            tolerance: extra.unwrap_or(DEFAULT_TOLERANCE),
+        })
+    }
+
+    /// How strongly the sRGB pixel `rgb` belongs to this entry, from 0 to 1,
+    /// on a page whose background is `background`.
+    ///
+    /// Antialiased edges are blends of the colour with the background, so a
+    /// pixel close to the line between them matches in proportion to how much
+    /// of the colour it holds. Blends with grey keep their OKLab hue, so hue
+    /// ranges fade edges out by chroma instead.
+    pub fn weight(&self, rgb: [f32; 3], background: [f32; 3]) -> f32 {
+        match *self {
+            Protect::Colour { rgb: c, tolerance } => {
+                let d = [0, 1, 2].map(|i| c[i] - background[i]);
+                let len2 = d.iter().map(|v| v * v).sum::<f32>();
+                let amount = if len2 < tolerance * tolerance {
+                    1.0
+                } else {
+                    let along = (0..3).map(|i| (rgb[i] - background[i]) * d[i]).sum::<f32>();
+                    (along / len2).clamp(0.0, 1.0)
+                };
+                let nearest = [0, 1, 2].map(|i| background[i] + d[i] * amount);
+                amount
+                    * (1.0 - color::smoothstep(tolerance, 2.0 * tolerance, distance(rgb, nearest)))
+            }
+            Protect::Hue {
+                from,
+                to,
+                min_chroma,
+            } => {
+                let [_, a, b] = color::linear_to_oklab(rgb.map(color::srgb_to_linear));
+                let chroma = a.hypot(b);
+                let hue = b.atan2(a).to_degrees().rem_euclid(360.0);
+                let span = (to - from).rem_euclid(360.0);
+                let into = (hue - from).rem_euclid(360.0);
+                // distance outside the range, in degrees
+                let outside = if into <= span {
+                    0.0
+                } else {
+                    (into - span).min(360.0 - into)
+                };
+                (1.0 - color::smoothstep(0.0, HUE_FEATHER, outside))
+                    * color::smoothstep(min_chroma * 0.5, min_chroma, chroma)
+            }
+        }
+    }
+}
+
+/// Colours that are restored after filtering, so meaning carried by colour,
+/// such as red failures and green passes, survives darkening.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Protected {
+    pub entries: Vec<Protect>,
+    pub mode: ProtectMode,
+}
+
+impl Protected {
+    /// Parses a comma separated list of [`Protect`] entries, such as
+    /// `#d73a49,#28a745~0.1,hue:80-110`.
+    pub fn parse(spec: &str, mode: ProtectMode) -> Result<Self, String> {
+        let entries = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(Protect::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Protected { entries, mode })
+    }
+
+    pub fn weight(&self, rgb: [f32; 3], background: [f32; 3]) -> f32 {
+        self.entries
+            .iter()
+            .map(|e| e.weight(rgb, background))
+            .fold(0.0, f32::max)
+    }
+
+    /// Blends protected pixels of `filtered` back towards `original`, which
+    /// must be the same size.
+    pub fn restore(&self, filtered: &mut Frame, original: &Frame) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let (background, _) = estimate_colours(original);
+        let mut last_in = None;
+        let mut last_out = (0.0, [0; 3]);
+        for (out, orig) in filtered.pixels_mut().zip(original.pixels()) {
+            let bgr = [orig[0], orig[1], orig[2]];
+            if last_in != Some(bgr) {
+                last_in = Some(bgr);
+                let rgb = [bgr[2], bgr[1], bgr[0]].map(|c| c as f32 / 255.0);
+                let weight = self.weight(rgb, background);
+                let target = if weight > 0.0 && self.mode == ProtectMode::Remap {
+                    let linear = [bgr[2], bgr[1], bgr[0]].map(color::decode);
+                    let (low, high) = REMAP_LIGHTNESS;
+                    let rgb = color::map_lightness_linear(linear, |l| {
+                        low + (high - low) * (1.0 - l).clamp(0.0, 1.0)
+                    });
+                    [rgb[2], rgb[1], rgb[0]].map(color::encode)
+                } else {
+                    bgr
+                };
+                last_out = (weight, target);
+            }
+            let (weight, target) = last_out;
+            if weight <= 0.0 {
+                continue;
+            }
+            for c in 0..3 {
+                out[c] =
+                    (out[c] as f32 + (target[c] as f32 - out[c] as f32) * weight).round() as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: [f32; 3] = [1.0; 3];
+    const DARK: [f32; 3] = [0.1, 0.1, 0.12];
+
+    fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+        [0, 1, 2].map(|i| a[i] * t + b[i] * (1.0 - t))
+    }
+
+    #[test]
+    fn edge_blends_match_in_proportion() {
+        let red = Protect::parse("#d73a49").unwrap();
+        let Protect::Colour { rgb, .. } = red else {
+            unreachable!()
+        };
+        for background in [WHITE, DARK] {
+            assert_eq!(red.weight(rgb, background), 1.0);
+            assert_eq!(red.weight(background, background), 0.0);
+            for amount in [0.25, 0.5, 0.75] {
+                let edge = mix(rgb, background, amount);
+                let weight = red.weight(edge, background);
+                assert!((weight - amount).abs() < 1e-4, "{} -> {}", amount, weight);
+                // rounding to 8 bits stays well inside the tolerance
+                let rounded = edge.map(|c| (c * 255.0).round() / 255.0);
+                assert!((red.weight(rounded, background) - amount).abs() < 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn other_blends_do_not_match() {
+        let red = Protect::parse("#d73a49").unwrap();
+        let blue = [0.0, 0.36, 0.77];
+        let green = [0.16, 0.65, 0.27];
+        for background in [WHITE, DARK] {
+            for amount in [0.25, 0.5, 0.75, 1.0] {
+                assert_eq!(red.weight(mix(blue, background, amount), background), 0.0);
+                assert_eq!(red.weight(mix(green, background, amount), background), 0.0);
+            }
+        }
+        // a grey edge of black text on white is not a faint red
+        assert_eq!(red.weight([0.5; 3], WHITE), 0.0);
+    }
+
+    #[test]
+    fn tolerance_fades_out() {
+        let red = Protect::parse("#d73a49~0.1").unwrap();
+        let rgb = [0.84, 0.23, 0.29];
+        let off = |d: f32| red.weight([rgb[0], rgb[1] + d, rgb[2]], WHITE);
+        assert!(off(0.05) > 0.9);
+        assert!(off(0.15) > 0.0 && off(0.15) < 0.9);
+        assert_eq!(off(0.4), 0.0);
+    }
+
+    #[test]
+    fn hue_range_fades_edges_by_chroma() {
+        let green = Protect::parse("hue:130-150").unwrap();
+        let rgb = [0.16, 0.65, 0.27];
+        assert_eq!(green.weight(rgb, WHITE), 1.0);
+        let weights = [0.8, 0.4, 0.1].map(|t| green.weight(mix(rgb, WHITE, t), WHITE));
+        assert!(weights[0] > weights[1] && weights[1] > weights[2]);
+        assert_eq!(weights[2], 0.0);
+        assert_eq!(green.weight([0.84, 0.23, 0.29], WHITE), 0.0);
+    }
+
+    #[test]
+    fn restore_blends_edges_back() {
+        let red = [73, 58, 215];
+        let mut original = Frame::new(4, 4);
+        for (i, p) in original.pixels_mut().enumerate() {
+            // mostly white page with one red antialiased edge
+            let t = match i {
+                0 => 1.0,
+                1 => 0.5,
+                _ => 0.0,
+            };
+            for c in 0..3 {
+                p[c] = (red[c] as f32 * t + 255.0 * (1.0 - t)).round() as u8;
+            }
+            p[3] = 255;
+        }
+        let mut filtered = Frame::new(4, 4);
+        let protected = Protected::parse("#d73a49", ProtectMode::Keep).unwrap();
+        protected.restore(&mut filtered, &original);
+        let px = |i: usize| &filtered.data[i * 4..i * 4 + 3];
+        assert_eq!(px(0), red);
+        // half the colour, half what the filter made of it
+        let half = original.data[4..7]
+            .iter()
+            .map(|&c| (c as f32 * 0.5).round() as u8);
+        assert!(px(1).iter().zip(half).all(|(&a, b)| a.abs_diff(b) <= 2));
+        assert_eq!(px(2), [0, 0, 0]);
+    }
+}
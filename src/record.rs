@@ -1,9 +1,12 @@
+use crate::format::PixelFormat;
+use crate::source::{FrameSource, Screenshot};
 use screenshot::{
     create_capture_item_for_monitor, create_d3d_device, create_direct3d_device,
     get_d3d_interface_from_object,
 };
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use windows::core::{ComInterface, IInspectable, Result};
 use windows::Foundation::TypedEventHandler;
 use windows::Graphics::{
@@ -30,10 +33,13 @@ pub struct ScreenRecorder {
     d3d_context: windows::Win32::Graphics::Direct3D11::ID3D11DeviceContext,
     frame_count: Arc<Mutex<usize>>,
     data: Arc<Mutex<Vec<u8>>>,
+    format: PixelFormat,
 }
 
 impl ScreenRecorder {
-    pub fn new(item: GraphicsCaptureItem) -> Result<Self> {
+    /// Captures `item` in `format`, which must be [`PixelFormat::Bgra8`] or
+    /// [`PixelFormat::Rgba16Float`] for HDR displays.
+    pub fn new(item: GraphicsCaptureItem, format: PixelFormat) -> Result<Self> {
         let item_size = item.Size()?;
 
         let d3d_device = create_d3d_device()?;
@@ -41,7 +47,10 @@ impl ScreenRecorder {
         let device = create_direct3d_device(&d3d_device)?;
         let frame_pool = Direct3D11CaptureFramePool::CreateFreeThreaded(
             &device,
-            DirectXPixelFormat::B8G8R8A8UIntNormalized,
+            match format {
+                PixelFormat::Bgra8 => DirectXPixelFormat::B8G8R8A8UIntNormalized,
+                PixelFormat::Rgba16Float => DirectXPixelFormat::R16G16B16A16Float,
+            },
             1,
             item_size,
         )?;
@@ -103,15 +112,16 @@ impl ScreenRecorder {
             d3d_context,
             frame_count,
             data,
+            format,
         })
     }
 
-    pub fn capture_primary() -> Result<Self> {
+    pub fn capture_primary(format: PixelFormat) -> Result<Self> {
         let monitor_handle =
             unsafe { MonitorFromWindow(GetDesktopWindow(), MONITOR_DEFAULTTOPRIMARY) };
         let item = create_capture_item_for_monitor(monitor_handle)?;
 
-        Self::new(item)
+        Self::new(item, format)
     }
 
     pub fn next(&self) -> Result<Screenshot> {
@@ -142,7 +152,7 @@ impl ScreenRecorder {
                 )
             };
 
-            let bytes_per_pixel = 4;
+            let bytes_per_pixel = self.format.bytes_per_pixel() as u32;
             let mut lock = self.data.lock().expect("poison mutex");
             let data: &mut Vec<u8> = lock.as_mut();
             let size = (desc.Width * desc.Height * bytes_per_pixel) as usize;
@@ -161,6 +171,11 @@ impl ScreenRecorder {
                 data: Arc::clone(&self.data),
                 height: self.item_size.Height as u32,
                 width: self.item_size.Width as u32,
+                stride: (desc.Width * bytes_per_pixel) as usize,
+                format: self.format,
+                // the primary monitor is always at the origin
+                origin: (0, 0),
+                timestamp: Instant::now(),
             }
         };
 
@@ -168,9 +183,8 @@ impl ScreenRecorder {
     }
 }
 
-#[derive(Default)]
-pub struct Screenshot {
-    pub data: Arc<Mutex<Vec<u8>>>,
-    pub width: u32,
-    pub height: u32,
+impl FrameSource for ScreenRecorder {
+    fn next(&mut self) -> std::result::Result<Screenshot, String> {
+        ScreenRecorder::next(self).map_err(|e| e.to_string())
+    }
 }
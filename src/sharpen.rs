@@ -0,0 +1,273 @@
+use crate::filter::{Filter, Frame, FrameInfo};
+
+/// Box blurs run one after the other to approximate a Gaussian.
+const PASSES: usize = 3;
+/// Luma is kept with 4 extra bits while blurring.
+const SCALE: u32 = 16;
+/// Removes the luma scale and the 8 bit fixed point amount.
+const SHIFT: u32 = 12;
+/// Largest box radius whose sums of scaled luma still fit in a `u16`,
+/// which gives a blur deviation of about 7.5 pixels.
+const MAX_R: usize = 7;
+
+/// Unsharp mask on luma, for thin fonts that wash out after darkening. Put it
+/// after the darkening filters in the pipeline. Only luma is sharpened, so
+/// coloured edges do not pick up fringes.
+///
+/// The blur is three box blurs. Rows sum the box directly, which vectorizes,
+/// and columns keep running sums, so the cost barely grows with `radius`.
+/// `tests::bench` times it on 1920x1080 noise: in a release build on one
+/// core of a 2.1GHz Xeon it takes 10-17ms for radius 1, 2 and 4, against
+/// 2ms for `invert`, which leaves room in the 30ms frame interval for the
+/// rest of the pipeline.
+pub struct Sharpen {
+    /// How much of the detail taken out by the blur is added back.
+    pub amount: f32,
+    /// Standard deviation of the blur, in pixels, up to about 7.5.
+    pub radius: f32,
+    luma: Vec<u16>,
+    blurred: Vec<u16>,
+    scratch: Vec<u16>,
+    padded: Vec<u16>,
+    sums: Vec<u16>,
+}
+
+impl Sharpen {
+    pub fn new(amount: f32, radius: f32) -> Self {
+        Sharpen {
+            amount,
+            radius,
+            luma: Vec::new(),
+            blurred: Vec::new(),
+            scratch: Vec::new(),
+            padded: Vec::new(),
+            sums: Vec::new(),
+        }
+    }
+
+    /// Parses `amount` or `amount/radius`, such as `0.8/1.5`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let number = |s: &str| {
+            s.parse::<f32>()
+                .map_err(|_| format!("invalid value '{}' for filter 'sharpen'", s))
+        };
+        Ok(match spec.split_once('/') {
+            Some((amount, radius)) => Sharpen::new(number(amount)?, number(radius)?),
+            None => Sharpen::new(number(spec)?, 1.0),
+        })
+    }
+}
+
+impl Default for Sharpen {
+    fn default() -> Self {
+        Sharpen::new(0.6, 1.0)
+    }
+}
+
+/// Fixed point reciprocal of a box width, to avoid dividing every pixel.
+fn reciprocal(r: usize) -> u32 {
+    (1 << 16) / (2 * r as u32 + 1)
+}
+
+/// Box sums start at `r` so that scaling them by the reciprocal rounds.
+fn average(sum: u16, inv: u32) -> u16 {
+    ((sum as u32 * inv) >> 16) as u16
+}
+
+/// Box blurs `src` into `dst`, repeating the edge pixels. Each offset in the
+/// box is added across the whole row, which vectorizes, where a running sum
+/// would wait on the previous pixel.
+fn blur_row(src: &[u16], dst: &mut [u16], padded: &mut Vec<u16>, r: usize) {
+    let width = src.len();
+    padded.clear();
+    padded.extend(std::iter::repeat_n(src[0], r));
+    padded.extend_from_slice(src);
+    padded.extend(std::iter::repeat_n(src[width - 1], r));
+    dst.fill(r as u16);
+    for i in 0..=2 * r {
+        for (d, &v) in dst.iter_mut().zip(&padded[i..i + width]) {
+            *d += v;
+        }
+    }
+    let inv = reciprocal(r);
+    for d in dst {
+        *d = average(*d, inv);
+    }
+}
+
+/// Box blurs each column of `src` into `dst`, repeating the edge rows. Works
+/// a row at a time to stay cache friendly.
+fn blur_columns(
+    src: &[u16],
+    dst: &mut [u16],
+    sums: &mut Vec<u16>,
+    width: usize,
+    height: usize,
+    r: usize,
+) {
+    let inv = reciprocal(r);
+    let row = |y: isize| {
+        let y = y.clamp(0, height as isize - 1) as usize;
+        &src[y * width..(y + 1) * width]
+    };
+    sums.clear();
+    sums.resize(width, r as u16);
+    for y in -(r as isize)..=r as isize {
+        for (sum, &v) in sums.iter_mut().zip(row(y)) {
+            *sum += v;
+        }
+    }
+    for (y, dst) in dst.chunks_exact_mut(width).enumerate() {
+        let (add, sub) = (row((y + r + 1) as isize), row(y as isize - r as isize));
+        for (((out, sum), &a), &s) in dst.iter_mut().zip(sums.iter_mut()).zip(add).zip(sub) {
+            *out = average(*sum, inv);
+            *sum = *sum + a - s;
+        }
+    }
+}
+
+impl Filter for Sharpen {
+    fn apply(&mut self, frame: &mut Frame, _info: &FrameInfo) {
+        let (width, height) = (frame.width, frame.height);
+        if width == 0 || height == 0 || self.amount == 0.0 {
+            return;
+        }
+        // box width whose three passes give a blur with this deviation
+        let box_width = (4.0 * self.radius * self.radius + 1.0).sqrt();
+        let r = ((box_width - 1.0) / 2.0).round().clamp(1.0, MAX_R as f32) as usize;
+
+        self.luma.resize(width * height, 0);
+        // weights sum to 256, which leaves luma scaled by SCALE
+        for (l, p) in self.luma.iter_mut().zip(frame.data.chunks_exact(4)) {
+            *l = (((18 * p[0] as u32 + 183 * p[1] as u32 + 55 * p[2] as u32) * SCALE) >> 8) as u16;
+        }
+        // rows are blurred while they are still in cache
+        self.blurred.resize(self.luma.len(), 0);
+        self.scratch.resize(width, 0);
+        for (src, dst) in self
+            .luma
+            .chunks_exact(width)
+            .zip(self.blurred.chunks_exact_mut(width))
+        {
+            blur_row(src, dst, &mut self.padded, r);
+            for _ in 1..PASSES {
+                blur_row(dst, &mut self.scratch, &mut self.padded, r);
+                dst.copy_from_slice(&self.scratch);
+            }
+        }
+        self.scratch.resize(self.luma.len(), 0);
+        for _ in 0..PASSES {
+            blur_columns(
+                &self.blurred,
+                &mut self.scratch,
+                &mut self.sums,
+                width,
+                height,
+                r,
+            );
+            std::mem::swap(&mut self.blurred, &mut self.scratch);
+        }
+
+        let amount = (self.amount * 256.0) as i32;
+        for ((p, &l), &b) in frame.pixels_mut().zip(&self.luma).zip(&self.blurred) {
+            let detail = ((l as i32 - b as i32) * amount) >> SHIFT;
+            // saturating byte arithmetic vectorizes, clamping does not
+            let (up, down) = (detail.clamp(0, 255) as u8, (-detail).clamp(0, 255) as u8);
+            for c in &mut p[..3] {
+                *c = c.saturating_add(up).saturating_sub(down);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Invert;
+    use std::time::{Duration, Instant};
+
+    /// A frame of pseudo random noise, so nothing is cheaper than it would
+    /// be on real content.
+    fn noise(width: usize, height: usize) -> Frame {
+        let mut frame = Frame::new(width, height);
+        let mut state = 0x2545_f491_u32;
+        for b in &mut frame.data {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            *b = state as u8;
+        }
+        frame
+    }
+
+    /// Median time `filter` takes on 1920x1080 noise.
+    fn time(filter: &mut impl Filter) -> Duration {
+        let source = noise(1920, 1080);
+        let info = FrameInfo::new(0, 0, 0);
+        let mut times: Vec<Duration> = (0..21)
+            .map(|_| {
+                let mut frame = source.clone();
+                let start = Instant::now();
+                filter.apply(&mut frame, &info);
+                start.elapsed()
+            })
+            .collect();
+        times.sort();
+        times[times.len() / 2]
+    }
+
+    fn grey(width: usize, height: usize, level: impl Fn(usize) -> u8) -> Frame {
+        let mut frame = Frame::new(width, height);
+        for (i, p) in frame.pixels_mut().enumerate() {
+            let v = level(i % width);
+            p.copy_from_slice(&[v, v, v, 255]);
+        }
+        frame
+    }
+
+    #[test]
+    fn flat_is_unchanged() {
+        for radius in [1.0, 2.0, 4.0, 5.0, 10.0] {
+            for v in [0, 1, 128, 254, 255] {
+                let mut frame = grey(40, 30, |_| v);
+                Sharpen::new(2.0, radius).apply(&mut frame, &FrameInfo::new(0, 0, 0));
+                assert!(frame.pixels().all(|p| p[..3] == [v; 3]), "{} {}", radius, v);
+            }
+        }
+    }
+
+    #[test]
+    fn edges_get_steeper() {
+        for radius in [1.0, 2.0, 4.0] {
+            let mut frame = grey(40, 12, |x| if x < 20 { 50 } else { 200 });
+            Sharpen::new(1.0, radius).apply(&mut frame, &FrameInfo::new(0, 0, 0));
+            let row: Vec<u8> = frame.data[6 * 160..7 * 160]
+                .iter()
+                .step_by(4)
+                .copied()
+                .collect();
+            assert!(row[19] < 50 && row[20] > 200, "{:?}", row);
+            assert_eq!((row[0], row[39]), (50, 200));
+            // every row sees the same vertical edge
+            let first = &frame.data[..160];
+            assert!(frame.data.chunks_exact(160).all(|r| r == first));
+        }
+    }
+
+    /// Run with
+    /// `cargo test --release --lib sharpen::tests::bench -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench() {
+        for radius in [1.0, 2.0, 4.0] {
+            let elapsed = time(&mut Sharpen::new(0.6, radius));
+            println!(
+                "sharpen radius {}: {:.1}ms",
+                radius,
+                elapsed.as_secs_f64() * 1e3
+            );
+        }
+        let elapsed = time(&mut Invert);
+        println!("invert: {:.1}ms", elapsed.as_secs_f64() * 1e3);
+    }
+}
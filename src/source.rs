@@ -0,0 +1,49 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::format::{PixelFormat, ToneMap};
+
+/// One captured frame of the screen.
+pub struct Screenshot {
+    pub data: Arc<Mutex<Vec<u8>>>,
+    pub width: u32,
+    pub height: u32,
+    /// Bytes from the start of one row to the start of the next.
+    pub stride: usize,
+    pub format: PixelFormat,
+    /// Screen position of the top-left pixel.
+    pub origin: (i32, i32),
+    /// When the frame was captured.
+    pub timestamp: Instant,
+}
+
+impl Screenshot {
+    /// Converts to tightly packed BGRA8, the layout the render loop works on.
+    /// Frames that already are pass through untouched.
+    pub fn to_bgra8(self, tone_map: &ToneMap) -> Screenshot {
+        let row_bytes = self.width as usize * self.format.bytes_per_pixel();
+        if self.format == PixelFormat::Bgra8 && self.stride == row_bytes {
+            return self;
+        }
+        let mut data = Vec::with_capacity(self.width as usize * self.height as usize * 4);
+        {
+            let src = self.data.lock().unwrap();
+            for row in src.chunks(self.stride).take(self.height as usize) {
+                self.format.to_bgra8(&row[..row_bytes], tone_map, &mut data);
+            }
+        }
+        Screenshot {
+            data: Arc::new(Mutex::new(data)),
+            stride: self.width as usize * 4,
+            format: PixelFormat::Bgra8,
+            ..self
+        }
+    }
+}
+
+/// Anything that can hand the render loop frames of the screen, such as
+/// `ScreenRecorder` on Windows.
+pub trait FrameSource {
+    /// Blocks until the next frame is available.
+    fn next(&mut self) -> Result<Screenshot, String>;
+}
@@ -0,0 +1,124 @@
+use crate::filter::{map_pixels, Filter, Frame, FrameInfo};
+
+pub const MIN_KELVIN: f32 = 1667.0;
+pub const MAX_KELVIN: f32 = 25000.0;
+pub const NEUTRAL_KELVIN: f32 = 6500.0;
+
+/// CIE 1931 chromaticity of a blackbody at `kelvin`, using the cubic spline
+/// fit of the Planckian locus by Kim et al. (2002).
+pub fn planckian_xy(kelvin: f32) -> [f32; 2] {
+    let t = kelvin.clamp(MIN_KELVIN, MAX_KELVIN) as f64;
+    let (t1, t2, t3) = (1e3 / t, 1e6 / (t * t), 1e9 / (t * t * t));
+    let x = if t <= 4000.0 {
+        -0.266_123_9 * t3 - 0.234_358_9 * t2 + 0.877_695_6 * t1 + 0.179_910
+    } else {
+        -3.025_846_9 * t3 + 2.107_037_9 * t2 + 0.222_634_7 * t1 + 0.240_390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.106_381_4 * x3 - 1.348_110_20 * x2 + 2.185_558_32 * x - 0.202_196_83
+    } else if t <= 4000.0 {
+        -0.954_947_6 * x3 - 1.374_185_93 * x2 + 2.091_370_15 * x - 0.167_488_67
+    } else {
+        3.081_758_0 * x3 - 5.873_386_70 * x2 + 3.751_129_97 * x - 0.370_014_83
+    };
+    [x as f32, y as f32]
+}
+
+/// Linear sRGB of a blackbody at `kelvin`, scaled so its largest channel is 1.
+pub fn blackbody_rgb(kelvin: f32) -> [f32; 3] {
+    let [x, y] = planckian_xy(kelvin);
+    let (cx, cy, cz) = (x / y, 1.0, (1.0 - x - y) / y);
+    let rgb = [
+        3.240_454_2 * cx - 1.537_138_5 * cy - 0.498_531_4 * cz,
+        -0.969_266 * cx + 1.876_010_8 * cy + 0.041_556 * cz,
+        0.055_643_4 * cx - 0.204_025_9 * cy + 1.057_225_2 * cz,
+    ]
+    .map(|c| c.max(0.0));
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    rgb.map(|c| c / max)
+}
+
+/// Linear channel gains that shift white from [`NEUTRAL_KELVIN`] to `kelvin`.
+/// The brightest channel keeps a gain of 1.
+pub fn white_balance(kelvin: f32) -> [f32; 3] {
+    let target = blackbody_rgb(kelvin);
+    let neutral = blackbody_rgb(NEUTRAL_KELVIN);
+    let gains = [0, 1, 2].map(|c| target[c] / neutral[c]);
+    let max = gains[0].max(gains[1]).max(gains[2]);
+    gains.map(|g| g / max)
+}
+
+/// Warms the frame to a colour temperature, like a night light. 6500K leaves
+/// the frame unchanged and lower values cut more blue.
+pub struct Temperature {
+    kelvin: f32,
+    gains: [f32; 3],
+}
+
+impl Temperature {
+    pub fn new(kelvin: f32) -> Self {
+        Temperature {
+            kelvin,
+            gains: white_balance(kelvin),
+        }
+    }
+
+    pub fn kelvin(&self) -> f32 {
+        self.kelvin
+    }
+}
+
+impl Filter for Temperature {
+    fn apply(&mut self, frame: &mut Frame, info: &FrameInfo) {
+        let gains = self.gains;
+        map_pixels(frame, info, |rgb| {
+            let lin = info.to_linear(rgb);
+            info.from_linear([0, 1, 2].map(|c| lin[c] * gains[c]))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neutral_is_identity() {
+        assert_eq!(white_balance(NEUTRAL_KELVIN), [1.0; 3]);
+        assert_eq!(Temperature::new(NEUTRAL_KELVIN).gains, [1.0; 3]);
+    }
+
+    #[test]
+    fn planckian_locus_matches_reference() {
+        // chromaticities of the Planckian locus from integrating Planck's law
+        // against the CIE 1931 2° observer
+        let reference = [
+            (2000.0, [0.5267, 0.4133]),
+            (3000.0, [0.4369, 0.4041]),
+            (4000.0, [0.3805, 0.3768]),
+            (5000.0, [0.3451, 0.3516]),
+        ];
+        for (kelvin, expected) in reference {
+            let xy = planckian_xy(kelvin);
+            for c in 0..2 {
+                assert!(
+                    (xy[c] - expected[c]).abs() < 5e-4,
+                    "{}K: {:?}, expected {:?}",
+                    kelvin,
+                    xy,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn warmer_cuts_blue() {
+        let [r, g, b] = white_balance(3400.0);
+        assert_eq!(r, 1.0);
+        assert!(b < g && g < r);
+        let warmer = white_balance(2000.0);
+        assert!(warmer[2] < b);
+    }
+}
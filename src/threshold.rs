@@ -0,0 +1,123 @@
+use crate::filter::{Filter, Frame, FrameInfo};
+use crate::palette::parse_hex;
+
+/// Sauvola's dynamic range of the standard deviation, for luma from 0 to 255.
+const RANGE: f32 = 128.0;
+
+/// High contrast reading mode: every pixel becomes either text or page, using
+/// Sauvola's adaptive threshold so uneven backgrounds and faint text still
+/// separate cleanly. Text is drawn in `foreground` on `background`, light on
+/// black by default. Light text on a dark page is detected and handled the
+/// same way.
+pub struct Threshold {
+    /// Colours as `[b, g, r]`, like the frame.
+    pub foreground: [u8; 3],
+    pub background: [u8; 3],
+    /// Half the size of the window local statistics are taken over.
+    pub radius: usize,
+    /// Sauvola's sensitivity. Higher values need darker text.
+    pub k: f32,
+    luma: Vec<u8>,
+    sums: Vec<u64>,
+    squares: Vec<u64>,
+}
+
+impl Default for Threshold {
+    fn default() -> Self {
+        Threshold {
+            foreground: [255, 255, 255],
+            background: [0, 0, 0],
+            radius: 12,
+            k: 0.2,
+            luma: Vec::new(),
+            sums: Vec::new(),
+            squares: Vec::new(),
+        }
+    }
+}
+
+impl Threshold {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Parses a colour pair such as `#ffd33d/#000000`, text colour first.
+    pub fn with_colours(spec: &str) -> Result<Self, String> {
+        let (fg, bg) = spec.split_once('/').ok_or_else(|| {
+            format!(
+                "invalid colours '{}', expected e.g. '#ffffff/#000000'",
+                spec
+            )
+        })?;
+        let colour = |s: &str| {
+            let [r, g, b] = parse_hex(s).ok_or_else(|| format!("invalid colour '{}'", s))?;
+            Ok::<_, String>([b, g, r].map(|c| (c * 255.0).round() as u8))
+        };
+        Ok(Threshold {
+            foreground: colour(fg)?,
+            background: colour(bg)?,
+            ..Default::default()
+        })
+    }
+}
+
+impl Filter for Threshold {
+    fn apply(&mut self, frame: &mut Frame, _info: &FrameInfo) {
+        let (width, height) = (frame.width, frame.height);
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.luma.clear();
+        self.luma.extend(frame.pixels().map(|p| {
+            (0.0722 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.2126 * p[2] as f32).round() as u8
+        }));
+        // Sauvola expects dark text on a light page
+        let total = self.luma.iter().map(|&l| l as u64).sum::<u64>();
+        if total < 128 * self.luma.len() as u64 {
+            self.luma.iter_mut().for_each(|l| *l = 255 - *l);
+        }
+
+        // integral images, one row and column larger than the frame
+        let stride = width + 1;
+        self.sums.clear();
+        self.sums.resize(stride * (height + 1), 0);
+        self.squares.clear();
+        self.squares.resize(stride * (height + 1), 0);
+        for y in 0..height {
+            let (mut sum, mut square) = (0, 0);
+            for x in 0..width {
+                let l = self.luma[y * width + x] as u64;
+                sum += l;
+                square += l * l;
+                let i = (y + 1) * stride + x + 1;
+                self.sums[i] = self.sums[i - stride] + sum;
+                self.squares[i] = self.squares[i - stride] + square;
+            }
+        }
+        let area = |table: &[u64], x0: usize, y0: usize, x1: usize, y1: usize| {
+            table[y1 * stride + x1] + table[y0 * stride + x0]
+                - table[y0 * stride + x1]
+                - table[y1 * stride + x0]
+        };
+
+        let r = self.radius;
+        for y in 0..height {
+            let (y0, y1) = (y.saturating_sub(r), (y + r + 1).min(height));
+            for x in 0..width {
+                let (x0, x1) = (x.saturating_sub(r), (x + r + 1).min(width));
+                let n = ((x1 - x0) * (y1 - y0)) as f32;
+                let mean = area(&self.sums, x0, y0, x1, y1) as f32 / n;
+                let variance = area(&self.squares, x0, y0, x1, y1) as f32 / n - mean * mean;
+                let deviation = variance.max(0.0).sqrt();
+                let threshold = mean * (1.0 + self.k * (deviation / RANGE - 1.0));
+                let i = y * width + x;
+                let colour = if (self.luma[i] as f32) < threshold {
+                    self.foreground
+                } else {
+                    self.background
+                };
+                frame.data[i * 4..i * 4 + 3].copy_from_slice(&colour);
+            }
+        }
+    }
+}
@@ -14,7 +14,9 @@ use windows::Win32::UI::WindowsAndMessaging::{
 };
 use winit::dpi::{PhysicalPosition, PhysicalSize};
 use winit::platform::windows::WindowExtWindows;
-use winit::window::Window;
+use winit::window::{Window, WindowLevel};
+
+use crate::platform::{Platform, TrackEvent};
 
 pub(crate) fn hide_from_capture(window: &Window) -> Result<()> {
     let hwnd = window.hwnd();
@@ -65,11 +67,6 @@ pub(crate) fn get_hwnd(window: &Window) -> HWND {
     HWND(hwnd as isize)
 }
 
-pub(crate) enum TrackEvent {
-    Size(PhysicalSize<u32>),
-    Position(PhysicalPosition<i32>),
-}
-
 pub(crate) fn track<F>(target: isize, callback: F)
 where
     F: Fn(Option<TrackEvent>) + Send + 'static,
@@ -112,3 +109,56 @@ pub(crate) fn get_foreground_hwnd() -> isize {
     let hwnd = unsafe { GetForegroundWindow() };
     hwnd.0
 }
+
+pub(crate) struct Win32;
+
+impl Platform for Win32 {
+    fn window_handle(window: &Window) -> isize {
+        window.hwnd() as isize
+    }
+
+    fn hide_from_capture(window: &Window) -> std::result::Result<(), String> {
+        hide_from_capture(window).map_err(|e| e.to_string())
+    }
+
+    fn set_parent(window: &Window, parent: isize) {
+        set_parent(window, parent)
+    }
+
+    fn set_transparent(window: &Window) {
+        set_transparent(window)
+    }
+
+    fn set_cursor_hittest(window: &Window, hittest: bool) {
+        window
+            .set_cursor_hittest(hittest)
+            .expect("could not set cursor hittest")
+    }
+
+    fn set_layered(window: &Window) {
+        set_layered(window)
+    }
+
+    fn set_noactivate(window: &Window) {
+        set_noactivate(window)
+    }
+
+    fn set_always_on_top(window: &Window) {
+        window.set_window_level(WindowLevel::AlwaysOnTop)
+    }
+
+    fn get_cursor_pos() -> (i32, i32) {
+        get_cursor_pos()
+    }
+
+    fn get_foreground_window() -> isize {
+        get_foreground_hwnd()
+    }
+
+    fn track<F>(target: isize, callback: F)
+    where
+        F: Fn(Option<TrackEvent>) + Send + 'static,
+    {
+        track(target, callback)
+    }
+}
@@ -0,0 +1,465 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::composite::{self, ConnectionExt as _, Redirect};
+use x11rb::protocol::randr::ConnectionExt as _;
+use x11rb::protocol::shm::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{
+    ConnectionExt as _, Drawable, ImageFormat, ImageOrder, MapState, Window, WindowClass,
+};
+use x11rb::rust_connection::RustConnection;
+
+use crate::format::PixelFormat;
+use crate::source::{FrameSource, Screenshot};
+
+/// X has no notification for screen changes that works everywhere, so the
+/// screen is polled at most this often.
+const MIN_INTERVAL: Duration = Duration::from_millis(16);
+
+/// A SysV shared memory segment the X server writes images into.
+struct Segment {
+    seg: shm::Seg,
+    addr: *mut libc::c_void,
+    size: usize,
+}
+
+impl Segment {
+    fn attach(conn: &RustConnection, size: usize) -> Result<Self, String> {
+        let id = unsafe { libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600) };
+        if id < 0 {
+            return Err("shmget failed".to_string());
+        }
+        let addr = unsafe { libc::shmat(id, std::ptr::null(), libc::SHM_RDONLY) };
+        let attached = if addr as isize == -1 {
+            Err("shmat failed".to_string())
+        } else {
+            conn.generate_id()
+                .map_err(|e| e.to_string())
+                .and_then(|seg| {
+                    conn.shm_attach(seg, id as u32, false)
+                        .map_err(|e| e.to_string())?
+                        .check()
+                        .map_err(|e| e.to_string())?;
+                    Ok(seg)
+                })
+        };
+        // the segment goes away once both sides have detached
+        unsafe { libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut()) };
+        match attached {
+            Ok(seg) => Ok(Segment { seg, addr, size }),
+            Err(e) => {
+                if addr as isize != -1 {
+                    unsafe { libc::shmdt(addr) };
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.addr as *const u8, self.size) }
+    }
+}
+
+/// Captures an area of the X screen, through MIT-SHM where the server
+/// supports it and plain `GetImage` otherwise, for example over the network.
+pub struct X11Recorder {
+    conn: RustConnection,
+    root: Window,
+    x: i16,
+    y: i16,
+    width: u16,
+    height: u16,
+    segment: Option<Segment>,
+    beneath: Option<Window>,
+    scratch: Vec<u8>,
+    data: Arc<Mutex<Vec<u8>>>,
+    last: Option<Instant>,
+}
+
+impl X11Recorder {
+    /// Captures the `width` x `height` area at (`x`, `y`) of the root window
+    /// of `screen` on `conn`.
+    fn new(
+        conn: RustConnection,
+        screen: usize,
+        x: i16,
+        y: i16,
+        width: u16,
+        height: u16,
+    ) -> Result<Self, String> {
+        let setup = conn.setup();
+        let root = &setup.roots[screen];
+        let bits = setup
+            .pixmap_formats
+            .iter()
+            .find(|f| f.depth == root.root_depth)
+            .map(|f| f.bits_per_pixel);
+        if bits != Some(32) || setup.image_byte_order != ImageOrder::LSB_FIRST {
+            return Err(format!(
+                "unsupported X pixel format: depth {}, {:?} bits per pixel",
+                root.root_depth, bits
+            ));
+        }
+        let root = root.root;
+
+        let size = width as usize * height as usize * 4;
+        let segment = match conn
+            .extension_information(shm::X11_EXTENSION_NAME)
+            .map_err(|e| e.to_string())?
+        {
+            Some(_) => Segment::attach(&conn, size)
+                .map_err(|e| println!("MIT-SHM unavailable, using GetImage: {}", e))
+                .ok(),
+            None => None,
+        };
+        Ok(X11Recorder {
+            conn,
+            root,
+            x,
+            y,
+            width,
+            height,
+            segment,
+            beneath: None,
+            scratch: vec![],
+            data: Arc::new(Mutex::new(vec![])),
+            last: None,
+        })
+    }
+
+    /// Captures the monitor RandR reports as primary, or the whole root
+    /// window if there is none.
+    pub fn capture_primary() -> Result<Self, String> {
+        let (conn, screen) = x11rb::connect(None).map_err(|e| e.to_string())?;
+        let root = conn.setup().roots[screen].root;
+        let primary = conn
+            .randr_get_monitors(root, true)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .and_then(|reply| reply.monitors.into_iter().find(|m| m.primary));
+        match primary {
+            Some(m) => Self::new(conn, screen, m.x, m.y, m.width, m.height),
+            None => {
+                let root = &conn.setup().roots[screen];
+                let (width, height) = (root.width_in_pixels, root.height_in_pixels);
+                Self::new(conn, screen, 0, 0, width, height)
+            }
+        }
+    }
+
+    /// Captures only the windows stacked below `window`, so an overlay
+    /// never sees its own output. Windows are read from the pixmaps the
+    /// Composite extension keeps for them, which also hold the parts the
+    /// overlay covers. Areas no window covers stay black.
+    pub fn beneath(mut self, window: Window) -> Result<Self, String> {
+        if self
+            .conn
+            .extension_information(composite::X11_EXTENSION_NAME)
+            .map_err(|e| e.to_string())?
+            .is_none()
+        {
+            return Err("X server lacks the Composite extension".to_string());
+        }
+        self.conn
+            .composite_query_version(0, 2)
+            .map_err(|e| e.to_string())?
+            .reply()
+            .map_err(|e| e.to_string())?;
+        // automatic redirection keeps the screen as it is, but gives every
+        // window a pixmap, also when no compositing manager runs
+        self.conn
+            .composite_redirect_subwindows(self.root, Redirect::AUTOMATIC)
+            .map_err(|e| e.to_string())?
+            .check()
+            .map_err(|e| e.to_string())?;
+        self.beneath = Some(window);
+        Ok(self)
+    }
+
+    /// Reads the `width` x `height` area at (`x`, `y`) of `drawable` into
+    /// `data`, tightly packed.
+    fn grab_drawable(
+        &mut self,
+        drawable: Drawable,
+        x: i16,
+        y: i16,
+        width: u16,
+        height: u16,
+        data: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        if let Some(segment) = &self.segment {
+            let reply = self
+                .conn
+                .shm_get_image(
+                    drawable,
+                    x,
+                    y,
+                    width,
+                    height,
+                    !0,
+                    ImageFormat::Z_PIXMAP.into(),
+                    segment.seg,
+                    0,
+                )
+                .map_err(|e| e.to_string())?
+                .reply();
+            match reply {
+                Ok(_) => {
+                    data.clear();
+                    data.extend_from_slice(&segment.data()[..width as usize * height as usize * 4]);
+                    return Ok(());
+                }
+                Err(e) => {
+                    println!("MIT-SHM capture failed, using GetImage: {}", e);
+                    self.segment = None;
+                }
+            }
+        }
+        let reply = self
+            .conn
+            .get_image(ImageFormat::Z_PIXMAP, drawable, x, y, width, height, !0)
+            .map_err(|e| e.to_string())?
+            .reply()
+            .map_err(|e| e.to_string())?;
+        *data = reply.data;
+        Ok(())
+    }
+
+    /// The child of the root window that holds `window`, which is the
+    /// window manager's frame once it has reparented it.
+    fn top_level(&self, mut window: Window) -> Option<Window> {
+        loop {
+            let parent = self.conn.query_tree(window).ok()?.reply().ok()?.parent;
+            if parent == self.root {
+                return Some(window);
+            }
+            if parent == x11rb::NONE {
+                return None;
+            }
+            window = parent;
+        }
+    }
+
+    /// Paints the windows stacked below `overlay` into `data`, bottom first.
+    fn grab_beneath(&mut self, overlay: Window, data: &mut Vec<u8>) -> Result<(), String> {
+        let top = self.top_level(overlay);
+        let tree = self
+            .conn
+            .query_tree(self.root)
+            .map_err(|e| e.to_string())?
+            .reply()
+            .map_err(|e| e.to_string())?;
+        // children come in stacking order, bottom first
+        let below = match tree.children.iter().position(|&w| Some(w) == top) {
+            Some(i) => &tree.children[..i],
+            None => &tree.children[..],
+        };
+
+        let cookies = below
+            .iter()
+            .map(|&w| {
+                let attributes = self.conn.get_window_attributes(w).ok()?;
+                let geometry = self.conn.get_geometry(w).ok()?;
+                Some((w, attributes, geometry))
+            })
+            .collect::<Vec<_>>();
+        let windows = cookies
+            .into_iter()
+            .flatten()
+            .filter_map(|(w, attributes, geometry)| {
+                let attributes = attributes.reply().ok()?;
+                let geometry = geometry.reply().ok()?;
+                let visible = attributes.map_state == MapState::VIEWABLE
+                    && attributes.class == WindowClass::INPUT_OUTPUT
+                    && (geometry.depth == 24 || geometry.depth == 32);
+                visible.then_some((w, geometry))
+            })
+            .collect::<Vec<_>>();
+
+        data.clear();
+        data.resize(self.width as usize * self.height as usize * 4, 0);
+        let mut scratch = std::mem::take(&mut self.scratch);
+        for (w, geometry) in windows {
+            // the pixmap includes the border
+            let border = geometry.border_width as i32 * 2;
+            let left = (geometry.x as i32).max(self.x as i32);
+            let top = (geometry.y as i32).max(self.y as i32);
+            let right = (geometry.x as i32 + geometry.width as i32 + border)
+                .min(self.x as i32 + self.width as i32);
+            let bottom = (geometry.y as i32 + geometry.height as i32 + border)
+                .min(self.y as i32 + self.height as i32);
+            if left >= right || top >= bottom {
+                continue;
+            }
+
+            let Ok(pixmap) = self.conn.generate_id() else {
+                continue;
+            };
+            // fails when the window went away since the query
+            let named = self
+                .conn
+                .composite_name_window_pixmap(w, pixmap)
+                .ok()
+                .and_then(|cookie| cookie.check().ok());
+            if named.is_none() {
+                continue;
+            }
+            let (width, height) = ((right - left) as usize, (bottom - top) as usize);
+            let grabbed = self.grab_drawable(
+                pixmap,
+                (left - geometry.x as i32) as i16,
+                (top - geometry.y as i32) as i16,
+                width as u16,
+                height as u16,
+                &mut scratch,
+            );
+            let _ = self.conn.free_pixmap(pixmap);
+            if grabbed.is_err() {
+                continue;
+            }
+
+            let stride = self.width as usize * 4;
+            let offset =
+                (top - self.y as i32) as usize * stride + (left - self.x as i32) as usize * 4;
+            for (row, src) in scratch.chunks_exact(width * 4).enumerate() {
+                let dst = offset + row * stride;
+                data[dst..dst + width * 4].copy_from_slice(src);
+            }
+        }
+        self.scratch = scratch;
+        Ok(())
+    }
+
+    fn grab(&mut self, data: &mut Vec<u8>) -> Result<(), String> {
+        match self.beneath {
+            Some(overlay) => self.grab_beneath(overlay, data),
+            None => self.grab_drawable(self.root, self.x, self.y, self.width, self.height, data),
+        }
+    }
+}
+
+impl Drop for X11Recorder {
+    fn drop(&mut self) {
+        if let Some(segment) = self.segment.take() {
+            let _ = self.conn.shm_detach(segment.seg);
+            let _ = self.conn.flush();
+            unsafe { libc::shmdt(segment.addr) };
+        }
+    }
+}
+
+impl FrameSource for X11Recorder {
+    fn next(&mut self) -> Result<Screenshot, String> {
+        if let Some(last) = self.last {
+            thread::sleep(MIN_INTERVAL.saturating_sub(last.elapsed()));
+        }
+        self.last = Some(Instant::now());
+
+        let data = Arc::clone(&self.data);
+        self.grab(&mut data.lock().unwrap())?;
+        Ok(Screenshot {
+            data,
+            width: self.width as u32,
+            height: self.height as u32,
+            stride: self.width as usize * 4,
+            format: PixelFormat::Bgra8,
+            origin: (self.x as i32, self.y as i32),
+            timestamp: Instant::now(),
+        })
+    }
+}
+
+/// These need an X server, so they are ignored by default. Run them with
+/// `xvfb-run -a -s "-screen 0 640x480x24" cargo test x11_record -- --ignored`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x11rb::protocol::xproto::CreateWindowAux;
+
+    /// Maps a window filled with `pixel` at (`x`, `y`) that no window
+    /// manager will move.
+    fn solid_window(conn: &RustConnection, x: i16, y: i16, size: u16, pixel: u32) -> Window {
+        let root = &conn.setup().roots[0];
+        let window = conn.generate_id().unwrap();
+        let aux = CreateWindowAux::new()
+            .background_pixel(pixel)
+            .override_redirect(1);
+        conn.create_window(
+            root.root_depth,
+            window,
+            root.root,
+            x,
+            y,
+            size,
+            size,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            root.root_visual,
+            &aux,
+        )
+        .unwrap();
+        conn.map_window(window).unwrap();
+        conn.get_input_focus().unwrap().reply().unwrap();
+        window
+    }
+
+    fn pixel(shot: &Screenshot, x: usize, y: usize) -> [u8; 3] {
+        let data = shot.data.lock().unwrap();
+        let p = &data[y * shot.stride + x * 4..][..3];
+        [p[0], p[1], p[2]]
+    }
+
+    fn record(x: i16, y: i16, width: u16, height: u16) -> X11Recorder {
+        let (conn, screen) = x11rb::connect(None).unwrap();
+        X11Recorder::new(conn, screen, x, y, width, height).unwrap()
+    }
+
+    /// Frames only change when the server has drawn, so give it a few.
+    fn settled(recorder: &mut X11Recorder, check: impl Fn(&Screenshot) -> bool) -> Screenshot {
+        for _ in 0..20 {
+            let shot = recorder.next().unwrap();
+            if check(&shot) {
+                return shot;
+            }
+        }
+        recorder.next().unwrap()
+    }
+
+    #[test]
+    #[ignore]
+    fn captures_area() {
+        let (conn, _) = x11rb::connect(None).expect("no X server on $DISPLAY");
+        solid_window(&conn, 10, 20, 40, 0xff0000);
+        solid_window(&conn, 60, 20, 40, 0x00ff00);
+
+        let mut recorder = record(0, 0, 120, 80);
+        let shot = settled(&mut recorder, |s| pixel(s, 80, 40) == [0, 255, 0]);
+        assert_eq!((shot.width, shot.height, shot.stride), (120, 80, 480));
+        assert_eq!(pixel(&shot, 30, 40), [0, 0, 255]);
+        assert_eq!(pixel(&shot, 80, 40), [0, 255, 0]);
+
+        // an area away from the origin starts at its own top left
+        let mut recorder = record(60, 20, 40, 40);
+        let shot = settled(&mut recorder, |s| pixel(s, 0, 0) == [0, 255, 0]);
+        assert_eq!(shot.origin, (60, 20));
+        let data = shot.data.lock().unwrap();
+        assert!(data.chunks_exact(4).all(|p| p[..3] == [0, 255, 0]));
+    }
+
+    #[test]
+    #[ignore]
+    fn beneath_leaves_out_overlay() {
+        let (conn, _) = x11rb::connect(None).expect("no X server on $DISPLAY");
+        solid_window(&conn, 200, 200, 60, 0x0000ff);
+        let overlay = solid_window(&conn, 210, 210, 40, 0xff0000);
+
+        let mut recorder = record(200, 200, 60, 60).beneath(overlay).unwrap();
+        let shot = settled(&mut recorder, |s| pixel(s, 30, 30) == [255, 0, 0]);
+        // the blue window shows through where the red overlay covers it
+        assert_eq!(pixel(&shot, 30, 30), [255, 0, 0]);
+        assert_eq!(pixel(&shot, 2, 2), [255, 0, 0]);
+    }
+}
@@ -0,0 +1,310 @@
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::platform::x11::WindowExtX11;
+use winit::window::Window;
+use x11rb::connection::Connection;
+use x11rb::protocol::shape::{ConnectionExt as _, SK, SO};
+use x11rb::protocol::xproto::{
+    AtomEnum, ClientMessageEvent, ClipOrdering, ConnectionExt as _, EventMask, MapState, PropMode,
+    Window as Xid,
+};
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+
+use crate::platform::{Platform, TrackEvent};
+
+/// `_NET_WM_STATE` client message action that adds states.
+const NET_WM_STATE_ADD: u32 = 1;
+/// `WM_HINTS` flag saying the input field is set.
+const INPUT_HINT: u32 = 1;
+
+/// Connection shared by everything here, since the cursor position is asked
+/// for on every event.
+fn connection() -> Option<&'static (RustConnection, Xid)> {
+    static CONN: OnceLock<Option<(RustConnection, Xid)>> = OnceLock::new();
+    CONN.get_or_init(|| {
+        let (conn, screen) = x11rb::connect(None)
+            .map_err(|e| println!("could not connect to X: {}", e))
+            .ok()?;
+        let root = conn.setup().roots[screen].root;
+        Some((conn, root))
+    })
+    .as_ref()
+}
+
+/// XID of a winit window, which is `None` when winit runs on Wayland.
+fn xid(window: &Window) -> Option<Xid> {
+    window.xlib_window().map(|id| id as Xid)
+}
+
+fn atom(conn: &RustConnection, name: &str) -> Option<u32> {
+    Some(
+        conn.intern_atom(false, name.as_bytes())
+            .ok()?
+            .reply()
+            .ok()?
+            .atom,
+    )
+}
+
+/// Adds `_NET_WM_STATE` atoms. Mapped windows have to ask the window
+/// manager, unmapped ones set the property it reads when they are mapped.
+fn add_wm_state(window: &Window, states: &[&str]) -> Option<()> {
+    let (conn, root) = connection()?;
+    let id = xid(window)?;
+    let wm_state = atom(conn, "_NET_WM_STATE")?;
+    let states = states
+        .iter()
+        .map(|s| atom(conn, s))
+        .collect::<Option<Vec<_>>>()?;
+    let mapped = conn.get_window_attributes(id).ok()?.reply().ok()?.map_state != MapState::UNMAPPED;
+    if mapped {
+        for pair in states.chunks(2) {
+            let second = pair.get(1).copied().unwrap_or(0);
+            let event = ClientMessageEvent::new(
+                32,
+                id,
+                wm_state,
+                [NET_WM_STATE_ADD, pair[0], second, 1, 0],
+            );
+            conn.send_event(
+                false,
+                *root,
+                EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
+                event,
+            )
+            .ok()?;
+        }
+    } else {
+        conn.change_property32(PropMode::APPEND, id, wm_state, AtomEnum::ATOM, &states)
+            .ok()?;
+    }
+    conn.flush().ok()
+}
+
+pub(crate) struct X11;
+
+impl Platform for X11 {
+    fn window_handle(window: &Window) -> isize {
+        xid(window).unwrap_or(0) as isize
+    }
+
+    fn hide_from_capture(_window: &Window) -> Result<(), String> {
+        // X11 has no way to keep a window out of captures, so the recorder
+        // skips it instead, see `X11Recorder::beneath`
+        Ok(())
+    }
+
+    fn set_parent(window: &Window, parent: isize) {
+        if let (Some((conn, _)), Some(id)) = (connection(), xid(window)) {
+            let _ = conn.reparent_window(id, parent as Xid, 0, 0);
+            let _ = conn.flush();
+        }
+    }
+
+    fn set_transparent(window: &Window) {
+        Self::set_cursor_hittest(window, false);
+    }
+
+    fn set_cursor_hittest(window: &Window, hittest: bool) {
+        // winit does not support this on X11, so the input shape is set
+        // directly: the default one covers the window, an empty one lets
+        // every click through
+        if let (Some((conn, _)), Some(id)) = (connection(), xid(window)) {
+            let set = if hittest {
+                conn.shape_mask(SO::SET, SK::INPUT, id, 0, 0, x11rb::NONE)
+            } else {
+                conn.shape_rectangles(SO::SET, SK::INPUT, ClipOrdering::UNSORTED, id, 0, 0, &[])
+            };
+            if let Err(e) = set {
+                println!("could not set input shape: {}", e);
+            }
+            let _ = conn.flush();
+        }
+    }
+
+    fn set_layered(_window: &Window) {
+        // X windows are composited as they are
+    }
+
+    fn set_noactivate(window: &Window) {
+        if let (Some((conn, _)), Some(id)) = (connection(), xid(window)) {
+            let hints = conn
+                .get_property(false, id, AtomEnum::WM_HINTS, AtomEnum::WM_HINTS, 0, 9)
+                .ok()
+                .and_then(|cookie| cookie.reply().ok())
+                .and_then(|reply| reply.value32().map(|v| v.collect::<Vec<_>>()));
+            let mut hints = hints.unwrap_or_default();
+            hints.resize(9, 0);
+            hints[0] |= INPUT_HINT;
+            hints[1] = 0;
+            let _ = conn.change_property32(
+                PropMode::REPLACE,
+                id,
+                AtomEnum::WM_HINTS,
+                AtomEnum::WM_HINTS,
+                &hints,
+            );
+        }
+        add_wm_state(
+            window,
+            &["_NET_WM_STATE_SKIP_TASKBAR", "_NET_WM_STATE_SKIP_PAGER"],
+        );
+    }
+
+    fn set_always_on_top(window: &Window) {
+        add_wm_state(window, &["_NET_WM_STATE_ABOVE"]);
+    }
+
+    fn get_cursor_pos() -> (i32, i32) {
+        connection()
+            .and_then(|(conn, root)| conn.query_pointer(*root).ok()?.reply().ok())
+            .map(|reply| (reply.root_x as i32, reply.root_y as i32))
+            .unwrap_or((0, 0))
+    }
+
+    fn get_foreground_window() -> isize {
+        connection()
+            .and_then(|(conn, root)| {
+                let active = atom(conn, "_NET_ACTIVE_WINDOW")?;
+                let reply = conn
+                    .get_property(false, *root, active, AtomEnum::WINDOW, 0, 1)
+                    .ok()?
+                    .reply()
+                    .ok()?;
+                let active = reply.value32()?.next();
+                active
+            })
+            .unwrap_or(0) as isize
+    }
+
+    fn track<F>(target: isize, callback: F)
+    where
+        F: Fn(Option<TrackEvent>) + Send + 'static,
+    {
+        thread::spawn(move || {
+            let Some((conn, root)) = connection() else {
+                return callback(None);
+            };
+            let target = target as Xid;
+            loop {
+                thread::sleep(Duration::from_millis(30));
+                let geometry = conn
+                    .get_geometry(target)
+                    .ok()
+                    .and_then(|cookie| cookie.reply().ok());
+                let origin = conn
+                    .translate_coordinates(target, *root, 0, 0)
+                    .ok()
+                    .and_then(|cookie| cookie.reply().ok());
+                let (Some(geometry), Some(origin)) = (geometry, origin) else {
+                    println!("tracked window {} is gone", target);
+                    callback(None);
+                    break;
+                };
+                callback(Some(TrackEvent::Position(PhysicalPosition {
+                    x: origin.dst_x as i32,
+                    y: origin.dst_y as i32,
+                })));
+                callback(Some(TrackEvent::Size(PhysicalSize {
+                    width: geometry.width as u32,
+                    height: geometry.height as u32,
+                })));
+            }
+        });
+    }
+}
+
+/// These need an X server with an EWMH window manager, so they are ignored
+/// by default. Run them with
+/// `xvfb-run -a sh -c 'openbox & sleep 1; cargo test x11_win -- --ignored'`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event_loop::EventLoopBuilder;
+    use winit::platform::x11::EventLoopBuilderExtX11;
+    use winit::window::WindowBuilder;
+
+    fn wm_state(conn: &RustConnection, id: Xid) -> Vec<u32> {
+        let wm_state = atom(conn, "_NET_WM_STATE").unwrap();
+        conn.get_property(false, id, wm_state, AtomEnum::ATOM, 0, 32)
+            .unwrap()
+            .reply()
+            .unwrap()
+            .value32()
+            .map(|v| v.collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    #[ignore]
+    fn overlay_window() {
+        let event_loop = EventLoopBuilder::new()
+            .with_x11()
+            .with_any_thread(true)
+            .build();
+        let window = WindowBuilder::new()
+            .with_inner_size(PhysicalSize::new(100, 100))
+            .build(&event_loop)
+            .unwrap();
+        X11::set_always_on_top(&window);
+        X11::set_transparent(&window);
+        X11::set_noactivate(&window);
+
+        let (conn, _) = connection().expect("no X server on $DISPLAY");
+        let id = xid(&window).unwrap();
+        assert_eq!(X11::window_handle(&window), id as isize);
+
+        // the window manager applies the states in its own time
+        let expected = [
+            "_NET_WM_STATE_ABOVE",
+            "_NET_WM_STATE_SKIP_TASKBAR",
+            "_NET_WM_STATE_SKIP_PAGER",
+        ]
+        .map(|name| atom(conn, name).unwrap());
+        let mut state = vec![];
+        for _ in 0..50 {
+            state = wm_state(conn, id);
+            if expected.iter().all(|a| state.contains(a)) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(40));
+        }
+        assert!(expected.iter().all(|a| state.contains(a)), "{:?}", state);
+
+        // no input region, so every click goes to the windows below
+        let input = conn
+            .shape_get_rectangles(id, SK::INPUT)
+            .unwrap()
+            .reply()
+            .unwrap();
+        assert!(input.rectangles.is_empty());
+
+        // the render loop turns clicks back on over the decorations
+        let input_rectangles = |hittest| {
+            X11::set_cursor_hittest(&window, hittest);
+            conn.shape_get_rectangles(id, SK::INPUT)
+                .unwrap()
+                .reply()
+                .unwrap()
+                .rectangles
+        };
+        let full = input_rectangles(true);
+        assert_eq!(full.len(), 1);
+        assert_eq!((full[0].width, full[0].height), (100, 100));
+        assert!(input_rectangles(false).is_empty());
+
+        let hints: Vec<u32> = conn
+            .get_property(false, id, AtomEnum::WM_HINTS, AtomEnum::WM_HINTS, 0, 9)
+            .unwrap()
+            .reply()
+            .unwrap()
+            .value32()
+            .unwrap()
+            .collect();
+        assert!(hints[0] & INPUT_HINT != 0 && hints[1] == 0);
+    }
+}